@@ -0,0 +1,9 @@
+-- Master password protecting endpoint secrets; at most one row.
+-- Endpoint secrets are encrypted by the application when it is set.
+CREATE TABLE secret_vault (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    kdf_salt TEXT NOT NULL,
+    verifier TEXT NOT NULL,
+    created_at TEXT DEFAULT CURRENT_TIMESTAMP,
+    updated_at TEXT DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,18 @@
+-- Cookies received from the servers of an endpoint, sent back on later
+-- requests to it. Session cookies have no expires_at.
+CREATE TABLE endpoint_cookie (
+    id TEXT PRIMARY KEY,
+    endpoint_id TEXT NOT NULL REFERENCES endpoint (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL,
+    domain TEXT NOT NULL,
+    host_only INTEGER NOT NULL DEFAULT 1,
+    path TEXT NOT NULL DEFAULT '/',
+    expires_at TEXT,
+    secure INTEGER NOT NULL DEFAULT 0,
+    http_only INTEGER NOT NULL DEFAULT 0,
+    same_site TEXT,
+    created_at TEXT DEFAULT CURRENT_TIMESTAMP,
+    updated_at TEXT DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (endpoint_id, name, domain, path)
+);
@@ -0,0 +1,3 @@
+-- Timing breakdown of the last execution of the request
+ALTER TABLE request_history
+ADD COLUMN timings TEXT;
@@ -0,0 +1,10 @@
+-- Introspected schema of an endpoint, kept out of its config so that
+-- refreshing it does not rewrite the config.
+ALTER TABLE endpoint ADD COLUMN schema_cache TEXT;
+ALTER TABLE endpoint ADD COLUMN schema_cached_at TEXT;
+
+UPDATE endpoint SET
+    schema_cache = json_extract(config, '$.graphql.schema_cache'),
+    schema_cached_at = json_extract(config, '$.graphql.schema_cached_at'),
+    config = json_remove(config, '$.graphql.schema_cache', '$.graphql.schema_cached_at')
+WHERE json_valid(config);
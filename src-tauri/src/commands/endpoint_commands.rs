@@ -1,7 +1,9 @@
 use sqlx::SqlitePool;
 use tauri::{command, AppHandle, Manager};
+use tauri_plugin_opener::OpenerExt;
 
 use crate::{
+    common::{client_certificate::ClientCertificate, oauth2::OAuthTokenCache},
     database::{entities::endpoint_entity::Endpoint, repositories::EndpointRepository},
     models::{
         common::pagination::PaginatedResponse,
@@ -60,3 +62,33 @@ pub async fn delete_endpoint(app_handle: AppHandle, dto: DeleteEndpointDto) -> R
         .await
         .map_err(|e| e.to_string())
 }
+
+/// Copies the files of a client certificate into the returned certificate
+/// so it can be stored with the endpoint, checking that it can be loaded.
+#[command]
+pub async fn import_client_certificate(
+    certificate: ClientCertificate,
+) -> Result<ClientCertificate, String> {
+    certificate.import()
+}
+
+/// Signs in to an OAuth2 endpoint with the authorization-code flow in the
+/// user's browser and saves the obtained tokens to the endpoint.
+#[command]
+pub async fn authorize_endpoint(app_handle: AppHandle, endpoint_id: String) -> Result<(), String> {
+    let pool = app_handle.state::<SqlitePool>();
+    let endpoint = EndpointRepository::find_by_id(&pool, &endpoint_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Endpoint not found: {}", endpoint_id))?;
+
+    app_handle
+        .state::<OAuthTokenCache>()
+        .authorize_interactively(&pool, &endpoint, |url| {
+            app_handle
+                .opener()
+                .open_url(url, None::<&str>)
+                .map_err(|e| format!("Failed to open the browser: {}", e))
+        })
+        .await
+}
@@ -0,0 +1,55 @@
+use chrono::Utc;
+use sqlx::SqlitePool;
+use tauri::{command, AppHandle, Manager};
+
+use crate::{
+    common::cookie_jar::{normalize_cookie, timestamp},
+    database::{
+        entities::endpoint_cookie_entity::EndpointCookie, repositories::EndpointCookieRepository,
+    },
+    models::endpoint_cookie_model::SaveEndpointCookieDto,
+};
+
+/// Lists the unexpired cookies in the jar of an endpoint.
+#[command]
+pub async fn get_endpoint_cookies(
+    app_handle: AppHandle,
+    endpoint_id: String,
+) -> Result<Vec<EndpointCookie>, String> {
+    let pool = app_handle.state::<SqlitePool>();
+    EndpointCookieRepository::find_by_endpoint(&pool, &endpoint_id, &timestamp(Utc::now()))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Adds a cookie to the jar of an endpoint, or edits it when `dto.id` is set.
+#[command]
+pub async fn save_endpoint_cookie(
+    app_handle: AppHandle,
+    dto: SaveEndpointCookieDto,
+) -> Result<EndpointCookie, String> {
+    let pool = app_handle.state::<SqlitePool>();
+    EndpointCookieRepository::save(&pool, normalize_cookie(dto)?)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn delete_endpoint_cookie(app_handle: AppHandle, id: String) -> Result<(), String> {
+    let pool = app_handle.state::<SqlitePool>();
+    EndpointCookieRepository::delete(&pool, &id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Removes all cookies of an endpoint, e.g. to log out.
+#[command]
+pub async fn clear_endpoint_cookies(
+    app_handle: AppHandle,
+    endpoint_id: String,
+) -> Result<(), String> {
+    let pool = app_handle.state::<SqlitePool>();
+    EndpointCookieRepository::delete_by_endpoint(&pool, &endpoint_id)
+        .await
+        .map_err(|e| e.to_string())
+}
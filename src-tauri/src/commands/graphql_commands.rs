@@ -1,10 +1,32 @@
-use crate::common::proxy_http::{proxy_http, HttpError, HttpRequest, HttpResponse};
+use crate::common::endpoint_request::{apply_endpoint_by_id, insert_header, send_with_endpoint};
+use crate::common::graphql_document::{
+    parse_operations, select_operation, uses_incremental_delivery, GraphQLOperation, OperationType,
+};
+use crate::common::graphql_introspection::{fetch_introspection, IntrospectedSchema};
+use crate::common::graphql_multipart::{send_incremental, INCREMENTAL_ACCEPT};
+use crate::common::graphql_sse::{stream_graphql_sse, GraphQLStreamMode};
+use crate::common::graphql_subscription::{SubscriptionEvent, SubscriptionRegistry};
+use crate::common::graphql_upload::upload_parts;
+use crate::common::http_client::RequestOptions;
+use crate::common::oauth2::OAuthTokenCache;
+use crate::common::proxy_http::{
+    proxy_http, use_unix_socket, HttpError, HttpHeader, HttpRequest, HttpResponse,
+};
+use crate::common::request_body::HttpBody;
+use crate::common::request_registry::RequestRegistry;
+use crate::database::repositories::EndpointRepository;
+use chrono::Utc;
+use log::warn;
+use reqwest::Url;
 use serde_json::Value;
+use sqlx::SqlitePool;
 use std::collections::HashMap;
-use tauri::command;
+use tauri::{command, ipc::Channel, AppHandle, Manager};
+use tokio::sync::oneshot;
 use typeshare::typeshare;
 
 #[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 struct GraphQLRequestBody {
     query: String,
     variables: Option<Value>,
@@ -25,11 +47,108 @@ pub struct SendGraphQLRequestPayload {
     pub query: String,
     /// Optional JSON string of variables for the GraphQL query
     pub variables: Option<String>,
+    /// Name of the operation to execute when the query contains several
+    pub operation_name: Option<String>,
+    /// Receive results as a graphql-sse event stream instead of a single
+    /// response; results are pushed through the `on_event` channel
+    pub stream_mode: Option<GraphQLStreamMode>,
+    /// Identifier under which a stream is registered, so that it can be
+    /// ended early with `stop_subscription`
+    pub stream_id: Option<String>,
+    /// Files to upload as a GraphQL multipart request, keyed by the
+    /// variable they are sent as (e.g. `variables.file` or
+    /// `variables.files.0`), with the path of the local file as value
+    pub files: Option<HashMap<String, String>>,
+    /// Network options such as timeout, redirects, TLS and proxy
+    pub options: Option<RequestOptions>,
+    /// Endpoint whose headers, authentication and options are applied to
+    /// the request, see `apply_endpoint` for the precedence
+    pub endpoint_id: Option<String>,
+}
+
+/// Lists the operations defined in a GraphQL document so the UI can offer
+/// a choice when there are several of them.
+#[command]
+pub async fn get_graphql_operations(query: String) -> Result<Vec<GraphQLOperation>, String> {
+    parse_operations(&query)
+}
+
+/// Returns the schema of an endpoint. The cached schema is served unless it
+/// is missing or `force_refresh` is set, in which case the server is
+/// introspected and the result stored as the endpoint's schema cache.
+#[command]
+pub async fn introspect_endpoint(
+    app_handle: AppHandle,
+    endpoint_id: String,
+    force_refresh: Option<bool>,
+) -> Result<IntrospectedSchema, String> {
+    let pool = app_handle.state::<SqlitePool>();
+    let endpoint = EndpointRepository::find_by_id(&pool, &endpoint_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Endpoint not found: {}", endpoint_id))?;
+
+    let graphql = endpoint
+        .config
+        .as_ref()
+        .and_then(|config| config.graphql.clone())
+        .unwrap_or_default();
+
+    if !force_refresh.unwrap_or(false) {
+        if let (Some(schema), Some(fetched_at)) = (graphql.schema_cache, graphql.schema_cached_at) {
+            return Ok(IntrospectedSchema {
+                endpoint_id,
+                schema,
+                fetched_at,
+                from_cache: true,
+            });
+        }
+    }
+
+    if !graphql.introspection_enabled {
+        return Err("Introspection is disabled for this endpoint".to_string());
+    }
+
+    let authorized = app_handle
+        .state::<OAuthTokenCache>()
+        .authorize(&pool, &endpoint)
+        .await?;
+    let schema = fetch_introspection(&authorized).await?;
+    let fetched_at = Utc::now().to_rfc3339();
+    EndpointRepository::update_schema_cache(&pool, &endpoint_id, &schema, &fetched_at)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(IntrospectedSchema {
+        endpoint_id,
+        schema,
+        fetched_at,
+        from_cache: false,
+    })
 }
 
+/// Sends a GraphQL request. When `request_id` is given the request can be
+/// aborted with `cancel_request`.
 #[command]
 pub async fn send_graphql_request(
+    app_handle: AppHandle,
+    data: SendGraphQLRequestPayload,
+    on_event: Channel<SubscriptionEvent>,
+    request_id: Option<String>,
+) -> Result<HttpResponse, HttpError> {
+    app_handle
+        .state::<RequestRegistry>()
+        .run(
+            request_id,
+            execute_graphql_request(&app_handle, data, on_event),
+        )
+        .await
+}
+
+async fn execute_graphql_request(
+    app_handle: &AppHandle,
     data: SendGraphQLRequestPayload,
+    on_event: Channel<SubscriptionEvent>,
 ) -> Result<HttpResponse, HttpError> {
     // Helper function to parse variables JSON string.
     let parse_variables = |variables: Option<String>| -> Result<Option<Value>, String> {
@@ -40,41 +159,180 @@ pub async fn send_graphql_request(
     };
 
     // Parse variables and construct the request body.
-    let variables_json = parse_variables(data.variables).map_err(|e| HttpError {
-        message: format!("Failed to parse variables: {}", e),
-    })?;
+    let variables_json = parse_variables(data.variables)
+        .map_err(|e| HttpError::new(format!("Failed to parse variables: {}", e)))?;
+    let operation_name = data.operation_name.filter(|name| !name.trim().is_empty());
+
+    // Resolve the operation up front so ambiguous documents fail early.
+    let operations = parse_operations(&data.query)
+        .map_err(|e| HttpError::new(format!("Failed to parse GraphQL document: {}", e)))?;
+    let operation =
+        select_operation(&operations, operation_name.as_deref()).map_err(HttpError::new)?;
+
+    // `@defer`/`@stream` results may arrive as a multipart response.
+    let incremental = uses_incremental_delivery(&data.query)
+        .map_err(|e| HttpError::new(format!("Failed to parse GraphQL document: {}", e)))?;
+
     let request_body = GraphQLRequestBody {
         query: data.query,
         variables: variables_json,
-        operation_name: None,
+        operation_name,
     };
 
     // Determine HTTP method and prepare headers.
     let method = data.method.as_deref().unwrap_or("POST").to_uppercase();
-    let mut headers = data.headers.unwrap_or_default();
-    headers.extend([
-        ("Content-Type".to_string(), "application/json".to_string()),
-        ("Accept".to_string(), "application/json".to_string()),
-        (
-            "User-Agent".to_string(),
-            "Query box GraphQL Client".to_string(),
-        ),
-    ]);
+    let mut headers: Vec<HttpHeader> = data
+        .headers
+        .unwrap_or_default()
+        .into_iter()
+        .map(HttpHeader::from)
+        .collect();
+    let accept = if incremental {
+        INCREMENTAL_ACCEPT
+    } else {
+        "application/json"
+    };
+    insert_header(&mut headers, "Accept".to_string(), accept.to_string());
+    insert_header(
+        &mut headers,
+        "User-Agent".to_string(),
+        "Query box GraphQL Client".to_string(),
+    );
+
+    // GET requests carry the operation in the URL, POST requests in the body,
+    // which is a multipart form when files are uploaded.
+    let files = data.files.filter(|files| !files.is_empty());
+    let (url, body) = match (method.as_str(), &files) {
+        ("GET", Some(_)) => {
+            return Err(HttpError::new(
+                "File uploads cannot be sent with GET, use POST instead",
+            ));
+        }
+        ("GET", None) => {
+            // Mutations must not be executed over GET (GraphQL-over-HTTP spec).
+            if operation.operation_type == OperationType::Mutation {
+                return Err(HttpError::new(
+                    "Mutations cannot be sent with GET, use POST instead",
+                ));
+            }
+            (build_get_url(&data.endpoint, &request_body)?, None)
+        }
+        ("POST", Some(files)) => {
+            let operations = serde_json::to_value(&request_body).map_err(|e| {
+                HttpError::new(format!("Failed to serialize GraphQL request body: {}", e))
+            })?;
+            let parts = upload_parts(operations, files).map_err(HttpError::new)?;
+            (data.endpoint, Some(HttpBody::Multipart(parts)))
+        }
+        ("POST", None) => {
+            insert_header(
+                &mut headers,
+                "Content-Type".to_string(),
+                "application/json".to_string(),
+            );
+            let body = serde_json::to_string(&request_body).map_err(|e| {
+                HttpError::new(format!("Failed to serialize GraphQL request body: {}", e))
+            })?;
+            (data.endpoint, Some(HttpBody::Json(body)))
+        }
+        _ => return Err(HttpError::new("GraphQL requests must use GET or POST")),
+    };
 
     // Construct the HttpRequest for proxy_http.
-    let http_request = HttpRequest {
-        method: method.clone(),
-        url: data.endpoint,
+    let mut http_request = HttpRequest {
+        method,
+        url,
         headers: Some(headers),
-        body: if method == "POST" {
-            Some(serde_json::to_string(&request_body).map_err(|e| HttpError {
-                message: format!("Failed to serialize GraphQL request body: {}", e),
-            })?)
-        } else {
-            None
-        },
+        body,
+        options: data.options,
+        endpoint_id: data.endpoint_id,
+        signing: None,
+        cookie_jar: None,
+    };
+    use_unix_socket(&mut http_request);
+    let pool = app_handle.state::<SqlitePool>();
+    let tokens = app_handle.state::<OAuthTokenCache>();
+
+    let Some(stream_mode) = data.stream_mode else {
+        let on_event = &on_event;
+        return send_with_endpoint(&pool, &tokens, http_request, |request| async move {
+            if incremental {
+                send_incremental(request, |event| {
+                    if let Err(e) = on_event.send(event) {
+                        warn!("Failed to deliver incremental result: {}", e);
+                    }
+                })
+                .await
+            } else {
+                // Call proxy_http and handle the response.
+                proxy_http(request).await
+            }
+        })
+        .await;
     };
 
-    // Call proxy_http and handle the response.
-    proxy_http(http_request).await
+    apply_endpoint_by_id(&pool, &tokens, &mut http_request).await?;
+    if http_request.method != "POST" {
+        return Err(HttpError::new("Event stream mode requires the POST method"));
+    }
+    if matches!(http_request.body, Some(HttpBody::Multipart(_))) {
+        return Err(HttpError::new(
+            "Event stream mode does not support file uploads",
+        ));
+    }
+    // Without a stream id nobody can stop the stream, so keep the sender
+    // alive until the server ends it. The registration is dropped with this
+    // future, also when the request is cancelled.
+    let registry = app_handle.state::<SubscriptionRegistry>();
+    let (_registration, _stop_sender, stop) = match data.stream_id {
+        Some(stream_id) => {
+            let (registration, stop) = registry.register_scoped(stream_id);
+            (Some(registration), None, stop)
+        }
+        None => {
+            let (sender, receiver) = oneshot::channel();
+            (None, Some(sender), receiver)
+        }
+    };
+
+    stream_graphql_sse(stream_mode, http_request, stop, |event| {
+        if let Err(e) = on_event.send(event) {
+            warn!("Failed to deliver stream event: {}", e);
+        }
+    })
+    .await
+}
+
+/// Encodes `query`, `variables` and `operationName` as URL query parameters,
+/// keeping any other parameters already present in the endpoint URL.
+fn build_get_url(endpoint: &str, request_body: &GraphQLRequestBody) -> Result<String, HttpError> {
+    let mut url =
+        Url::parse(endpoint).map_err(|e| HttpError::new(format!("Invalid endpoint URL: {}", e)))?;
+
+    let retained_pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| !matches!(key.as_ref(), "query" | "variables" | "operationName"))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+
+    let variables = request_body
+        .variables
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| HttpError::new(format!("Failed to serialize variables: {}", e)))?;
+
+    {
+        let mut pairs = url.query_pairs_mut();
+        pairs.clear().extend_pairs(retained_pairs);
+        pairs.append_pair("query", &request_body.query);
+        if let Some(variables) = &variables {
+            pairs.append_pair("variables", variables);
+        }
+        if let Some(operation_name) = &request_body.operation_name {
+            pairs.append_pair("operationName", operation_name);
+        }
+    }
+
+    Ok(url.into())
 }
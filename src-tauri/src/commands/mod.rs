@@ -7,12 +7,21 @@ pub use settings_commands::*;
 pub mod endpoint_commands;
 pub use endpoint_commands::*;
 
+pub mod endpoint_cookie_commands;
+pub use endpoint_cookie_commands::*;
+
 pub mod request_history_commands;
 pub use request_history_commands::*;
 
 pub mod proxy_http_commands;
 pub use proxy_http_commands::*;
 
+pub mod subscription_commands;
+pub use subscription_commands::*;
+
+pub mod secret_vault_commands;
+pub use secret_vault_commands::*;
+
 ///
 /// # Command Registration Macro
 ///
@@ -37,6 +46,8 @@ macro_rules! app_commands {
     () => {
         tauri::generate_handler![
             commands::send_graphql_request,
+            commands::get_graphql_operations,
+            commands::introspect_endpoint,
             commands::get_all_settings,
             commands::get_settings_by_category,
             commands::get_settings_map,
@@ -50,12 +61,27 @@ macro_rules! app_commands {
             commands::create_endpoint,
             commands::update_endpoint,
             commands::delete_endpoint,
+            commands::import_client_certificate,
+            commands::authorize_endpoint,
+            commands::get_endpoint_cookies,
+            commands::save_endpoint_cookie,
+            commands::delete_endpoint_cookie,
+            commands::clear_endpoint_cookies,
             commands::get_all_request_histories,
             commands::create_request_history,
             commands::update_request_history,
             commands::delete_request_history,
             commands::set_active_request_history,
             commands::proxy_http_request,
+            commands::save_response_to_file,
+            commands::cancel_request,
+            commands::start_subscription,
+            commands::stop_subscription,
+            commands::get_secret_vault_status,
+            commands::set_master_password,
+            commands::change_master_password,
+            commands::unlock_secret_vault,
+            commands::lock_secret_vault,
         ]
     };
 }
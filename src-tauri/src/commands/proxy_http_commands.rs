@@ -1,7 +1,67 @@
-use crate::common::proxy_http::{proxy_http, HttpError, HttpRequest, HttpResponse};
-use tauri::command;
+use crate::common::endpoint_request::send_with_endpoint;
+use crate::common::oauth2::OAuthTokenCache;
+use crate::common::proxy_http::{
+    proxy_http, save_http, use_unix_socket, HttpError, HttpRequest, HttpResponse,
+};
+use crate::common::request_registry::RequestRegistry;
+use sqlx::SqlitePool;
+use std::path::PathBuf;
+use tauri::{command, AppHandle, Manager};
 
+/// Sends a request, applying the configuration of `request.endpoint_id`
+/// when set. `unix://` URLs are sent over a Unix domain socket, see
+/// `use_unix_socket`. When `request_id` is given the request can be aborted with
+/// `cancel_request`.
 #[command]
-pub async fn proxy_http_request(request: HttpRequest) -> Result<HttpResponse, HttpError> {
-    proxy_http(request).await
+pub async fn proxy_http_request(
+    app_handle: AppHandle,
+    mut request: HttpRequest,
+    request_id: Option<String>,
+) -> Result<HttpResponse, HttpError> {
+    use_unix_socket(&mut request);
+    let pool = app_handle.state::<SqlitePool>();
+    let tokens = app_handle.state::<OAuthTokenCache>();
+    app_handle
+        .state::<RequestRegistry>()
+        .run(
+            request_id,
+            send_with_endpoint(&pool, &tokens, request, proxy_http),
+        )
+        .await
+}
+
+/// Sends a request like `proxy_http_request` but streams the response body
+/// to the file at `path`, for downloads too large to hold in memory. The
+/// returned response's `body` is the path.
+#[command]
+pub async fn save_response_to_file(
+    app_handle: AppHandle,
+    mut request: HttpRequest,
+    path: String,
+    request_id: Option<String>,
+) -> Result<HttpResponse, HttpError> {
+    use_unix_socket(&mut request);
+    let pool = app_handle.state::<SqlitePool>();
+    let tokens = app_handle.state::<OAuthTokenCache>();
+    let path = PathBuf::from(path);
+    app_handle
+        .state::<RequestRegistry>()
+        .run(
+            request_id,
+            send_with_endpoint(&pool, &tokens, request, |request| {
+                save_http(request, path.clone())
+            }),
+        )
+        .await
+}
+
+/// Aborts an in-flight `proxy_http_request`, `save_response_to_file` or
+/// `send_graphql_request`, which then fails with a `Cancelled` error.
+#[command]
+pub async fn cancel_request(app_handle: AppHandle, request_id: String) -> Result<(), String> {
+    if app_handle.state::<RequestRegistry>().cancel(&request_id) {
+        Ok(())
+    } else {
+        Err(format!("Request not in flight: {}", request_id))
+    }
 }
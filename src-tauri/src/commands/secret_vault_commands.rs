@@ -0,0 +1,45 @@
+use sqlx::SqlitePool;
+use tauri::{command, AppHandle, Manager};
+
+use crate::common::{
+    oauth2::OAuthTokenCache,
+    secret_vault::{self, SecretVaultStatus},
+};
+
+#[command]
+pub async fn get_secret_vault_status() -> Result<SecretVaultStatus, String> {
+    Ok(secret_vault::status())
+}
+
+/// Sets a master password and encrypts the secrets of all endpoints with
+/// a key derived from it.
+#[command]
+pub async fn set_master_password(app_handle: AppHandle, password: String) -> Result<(), String> {
+    let pool = app_handle.state::<SqlitePool>();
+    secret_vault::enable(&pool, &password).await
+}
+
+/// Replaces the master password, re-encrypting all endpoint secrets.
+#[command]
+pub async fn change_master_password(
+    app_handle: AppHandle,
+    current_password: String,
+    new_password: String,
+) -> Result<(), String> {
+    let pool = app_handle.state::<SqlitePool>();
+    secret_vault::change_password(&pool, &current_password, &new_password).await
+}
+
+#[command]
+pub async fn unlock_secret_vault(app_handle: AppHandle, password: String) -> Result<(), String> {
+    let pool = app_handle.state::<SqlitePool>();
+    secret_vault::unlock(&pool, &password).await
+}
+
+/// Locks endpoint secrets again and drops the OAuth2 tokens held in memory.
+#[command]
+pub async fn lock_secret_vault(app_handle: AppHandle) -> Result<(), String> {
+    secret_vault::lock()?;
+    app_handle.state::<OAuthTokenCache>().clear().await;
+    Ok(())
+}
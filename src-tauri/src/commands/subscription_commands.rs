@@ -0,0 +1,189 @@
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use tauri::{command, ipc::Channel, AppHandle, Manager};
+use typeshare::typeshare;
+use uuid::Uuid;
+
+use crate::{
+    common::{
+        endpoint_request::{apply_api_key_placement, endpoint_headers, merge_headers},
+        graphql_document::{parse_operations, select_operation},
+        graphql_subscription::{
+            derive_ws_url, run_subscription, SubscriptionEvent, SubscriptionProtocol,
+            SubscriptionRegistry, SubscriptionRequest,
+        },
+        oauth2::OAuthTokenCache,
+        proxy_http::HttpHeader,
+    },
+    database::repositories::EndpointRepository,
+};
+
+/// Headers that belong to the WebSocket handshake itself or only make sense
+/// for regular HTTP requests, so they are not forwarded on upgrade.
+const SKIPPED_HANDSHAKE_HEADERS: [&str; 7] = [
+    "host",
+    "connection",
+    "upgrade",
+    "content-type",
+    "content-length",
+    "accept",
+    "user-agent",
+];
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[typeshare]
+pub struct StartSubscriptionPayload {
+    /// The GraphQL endpoint URL, used to derive a ws:// URL when no
+    /// subscription URL is configured
+    pub endpoint: String,
+    /// Saved endpoint to read `subscription_url`, headers and auth from
+    pub endpoint_id: Option<String>,
+    /// Explicit WebSocket URL, overriding the endpoint configuration
+    pub subscription_url: Option<String>,
+    /// Subprotocol to use; negotiated with the server when omitted
+    pub protocol: Option<SubscriptionProtocol>,
+    /// Optional headers, merged over the endpoint headers
+    pub headers: Option<HashMap<String, String>>,
+    /// Optional JSON string sent as the `connection_init` payload instead of
+    /// the merged headers
+    pub connection_params: Option<String>,
+    /// The GraphQL subscription document
+    pub query: String,
+    /// Optional JSON string of variables for the subscription
+    pub variables: Option<String>,
+    /// Name of the operation to execute when the query contains several
+    pub operation_name: Option<String>,
+}
+
+/// Opens a GraphQL subscription over WebSocket and streams its events
+/// through `on_event`. Returns the id to pass to `stop_subscription`.
+#[command]
+pub async fn start_subscription(
+    app_handle: AppHandle,
+    data: StartSubscriptionPayload,
+    on_event: Channel<SubscriptionEvent>,
+) -> Result<String, String> {
+    let operation_name = data.operation_name.filter(|name| !name.trim().is_empty());
+    let operations = parse_operations(&data.query)
+        .map_err(|e| format!("Failed to parse GraphQL document: {}", e))?;
+    select_operation(&operations, operation_name.as_deref())?;
+
+    let parse_json = |value: Option<String>, what: &str| -> Result<Option<Value>, String> {
+        value
+            .filter(|v| !v.trim().is_empty())
+            .map(|v| {
+                serde_json::from_str(&v).map_err(|e| format!("Failed to parse {}: {}", what, e))
+            })
+            .transpose()
+    };
+    let variables = parse_json(data.variables, "variables")?;
+    let connection_params = parse_json(data.connection_params, "connection params")?;
+
+    let endpoint = match &data.endpoint_id {
+        Some(endpoint_id) => {
+            let pool = app_handle.state::<SqlitePool>();
+            let endpoint = EndpointRepository::find_by_id(&pool, endpoint_id)
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("Endpoint not found: {}", endpoint_id))?;
+            Some(
+                app_handle
+                    .state::<OAuthTokenCache>()
+                    .authorize(&pool, &endpoint)
+                    .await?,
+            )
+        }
+        None => None,
+    };
+
+    let configured_url = endpoint
+        .as_ref()
+        .and_then(|endpoint| endpoint.config.as_ref())
+        .and_then(|config| config.graphql.as_ref())
+        .and_then(|graphql| graphql.subscription_url.clone());
+    let mut url = match data
+        .subscription_url
+        .or(configured_url)
+        .filter(|url| !url.trim().is_empty())
+    {
+        Some(url) => url,
+        None => derive_ws_url(&data.endpoint)?,
+    };
+
+    let mut headers = endpoint.as_ref().map(endpoint_headers).unwrap_or_default();
+    merge_headers(
+        &mut headers,
+        data.headers
+            .unwrap_or_default()
+            .into_iter()
+            .map(HttpHeader::from)
+            .collect(),
+    );
+    if let Some(auth) = endpoint
+        .as_ref()
+        .and_then(|endpoint| endpoint.auth.as_ref())
+    {
+        apply_api_key_placement(auth, &mut url, &mut headers)?;
+    }
+    let connection_params = connection_params.or_else(|| {
+        (!headers.is_empty()).then(|| {
+            Value::Object(
+                headers
+                    .iter()
+                    .map(|header| (header.name.clone(), Value::String(header.value.clone())))
+                    .collect(),
+            )
+        })
+    });
+    headers.retain(|header| {
+        let name = header.name.to_ascii_lowercase();
+        !SKIPPED_HANDSHAKE_HEADERS.contains(&name.as_str()) && !name.starts_with("sec-websocket-")
+    });
+
+    let request = SubscriptionRequest {
+        url,
+        protocol: data.protocol,
+        headers,
+        connection_params,
+        query: data.query,
+        variables,
+        operation_name,
+    };
+
+    let subscription_id = Uuid::new_v4().to_string();
+    let stop = app_handle
+        .state::<SubscriptionRegistry>()
+        .register(subscription_id.clone());
+
+    let id = subscription_id.clone();
+    tauri::async_runtime::spawn(async move {
+        run_subscription(request, stop, |event| {
+            if let Err(e) = on_event.send(event) {
+                warn!("Failed to deliver subscription event for {}: {}", id, e);
+            }
+        })
+        .await;
+        app_handle.state::<SubscriptionRegistry>().remove(&id);
+    });
+
+    Ok(subscription_id)
+}
+
+#[command]
+pub async fn stop_subscription(
+    app_handle: AppHandle,
+    subscription_id: String,
+) -> Result<(), String> {
+    if app_handle
+        .state::<SubscriptionRegistry>()
+        .stop(&subscription_id)
+    {
+        Ok(())
+    } else {
+        Err(format!("Subscription not running: {}", subscription_id))
+    }
+}
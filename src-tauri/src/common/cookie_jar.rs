@@ -0,0 +1,283 @@
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use cookie::Cookie;
+use log::{debug, warn};
+use reqwest::header::{HeaderMap, HeaderValue, COOKIE, SET_COOKIE};
+use reqwest::{Request, Url};
+use sqlx::SqlitePool;
+use std::net::IpAddr;
+
+use crate::common::secret_vault;
+use crate::database::entities::endpoint_cookie_entity::EndpointCookie;
+use crate::database::repositories::EndpointCookieRepository;
+use crate::models::endpoint_cookie_model::SaveEndpointCookieDto;
+
+/// The cookie jar of an endpoint, stored in the database. `send_http` adds
+/// its cookies to requests and stores the `Set-Cookie` headers of the
+/// responses, following RFC 6265, including those of redirects.
+#[derive(Debug, Clone)]
+pub struct CookieJar {
+    pool: SqlitePool,
+    endpoint_id: String,
+}
+
+impl CookieJar {
+    pub fn new(pool: SqlitePool, endpoint_id: impl Into<String>) -> Self {
+        Self {
+            pool,
+            endpoint_id: endpoint_id.into(),
+        }
+    }
+
+    /// Adds the cookies matching the request's URL to its `Cookie` header.
+    /// Cookies the request already sets keep their value.
+    pub async fn add_cookies(&self, request: &mut Request) -> Result<(), String> {
+        let now = timestamp(Utc::now());
+        let cookies =
+            EndpointCookieRepository::find_by_endpoint(&self.pool, &self.endpoint_id, &now)
+                .await
+                .map_err(|e| format!("Failed to load cookies: {}", e))?;
+
+        let existing: Vec<&str> = request
+            .headers()
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+            .collect();
+        let mut matching: Vec<&EndpointCookie> = cookies
+            .iter()
+            .filter(|cookie| matches(cookie, request.url()))
+            // Values stay encrypted while the vault is locked.
+            .filter(|cookie| !secret_vault::is_sealed(&cookie.value))
+            .filter(|cookie| {
+                !existing
+                    .iter()
+                    .any(|pair| pair.split('=').next().map(str::trim) == Some(&cookie.name))
+            })
+            .collect();
+        if matching.is_empty() {
+            return Ok(());
+        }
+        // Cookies with longer paths are listed first.
+        matching.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+
+        let value = existing
+            .into_iter()
+            .map(str::to_string)
+            .chain(
+                matching
+                    .iter()
+                    .map(|cookie| format!("{}={}", cookie.name, cookie.value)),
+            )
+            .collect::<Vec<_>>()
+            .join("; ");
+        let value = HeaderValue::from_str(&value).map_err(|e| format!("Invalid cookie: {}", e))?;
+        request.headers_mut().insert(COOKIE, value);
+        Ok(())
+    }
+
+    /// Stores the cookies set by a response from `url` and removes those
+    /// the server expired. Failures are only logged since the response has
+    /// been received.
+    pub async fn store(&self, url: &Url, headers: &HeaderMap) {
+        let now = Utc::now();
+        for header in headers.get_all(SET_COOKIE) {
+            let Some(cookie) = header
+                .to_str()
+                .ok()
+                .and_then(|header| self.parse(url, header, now))
+            else {
+                debug!("Ignoring cookie set by {}: {:?}", url, header);
+                continue;
+            };
+
+            let expired = cookie
+                .expires_at
+                .as_deref()
+                .is_some_and(|expires_at| expires_at <= timestamp(now).as_str());
+            let result = if expired {
+                EndpointCookieRepository::remove(
+                    &self.pool,
+                    &self.endpoint_id,
+                    &cookie.name,
+                    &cookie.domain,
+                    cookie.path.as_deref().unwrap_or("/"),
+                )
+                .await
+                .map_err(anyhow::Error::from)
+            } else {
+                EndpointCookieRepository::save(&self.pool, cookie)
+                    .await
+                    .map(|_| ())
+            };
+            if let Err(e) = result {
+                warn!("Failed to store cookie set by {}: {}", url, e);
+            }
+        }
+
+        if let Err(e) =
+            EndpointCookieRepository::delete_expired(&self.pool, &self.endpoint_id, &timestamp(now))
+                .await
+        {
+            warn!("Failed to remove expired cookies: {}", e);
+        }
+    }
+
+    /// Reads a `Set-Cookie` header, or `None` when the cookie must be
+    /// ignored because it is malformed or set for another domain.
+    fn parse(&self, url: &Url, header: &str, now: DateTime<Utc>) -> Option<SaveEndpointCookieDto> {
+        let cookie = Cookie::parse(header).ok()?;
+        if cookie.name().is_empty() || (cookie.secure() == Some(true) && !is_secure(url)) {
+            return None;
+        }
+
+        let host = url.host_str()?.to_ascii_lowercase();
+        let domain = cookie
+            .domain()
+            .map(|domain| domain.trim_start_matches('.').to_ascii_lowercase())
+            .filter(|domain| !domain.is_empty());
+        let (domain, host_only) = match domain {
+            Some(domain) if domain == host => (domain, false),
+            // Without a public suffix list, at least refuse top-level
+            // domains such as `com`.
+            Some(domain)
+                if domain.contains('.')
+                    && domain_matches(&host, &domain)
+                    && url.domain().is_some() =>
+            {
+                (domain, false)
+            }
+            Some(_) => return None,
+            None => (host, true),
+        };
+
+        let path = cookie
+            .path()
+            .filter(|path| path.starts_with('/'))
+            .map_or_else(|| default_path(url), str::to_string);
+        let expires_at = match cookie.max_age() {
+            Some(max_age) if max_age.is_negative() => Some(now),
+            Some(max_age) => TimeDelta::try_seconds(max_age.whole_seconds())
+                .and_then(|max_age| now.checked_add_signed(max_age)),
+            None => cookie
+                .expires_datetime()
+                .and_then(|expires| DateTime::from_timestamp(expires.unix_timestamp(), 0)),
+        };
+
+        Some(SaveEndpointCookieDto {
+            id: None,
+            endpoint_id: self.endpoint_id.clone(),
+            name: cookie.name().to_string(),
+            value: cookie.value().to_string(),
+            domain,
+            host_only: Some(host_only),
+            path: Some(path),
+            expires_at: expires_at.map(timestamp),
+            secure: Some(cookie.secure().unwrap_or(false)),
+            http_only: Some(cookie.http_only().unwrap_or(false)),
+            same_site: cookie.same_site().map(|same_site| same_site.to_string()),
+        })
+    }
+}
+
+/// Checks a cookie entered by the user and brings it into the form stored
+/// for cookies received from servers.
+pub fn normalize_cookie(mut dto: SaveEndpointCookieDto) -> Result<SaveEndpointCookieDto, String> {
+    dto.name = dto.name.trim().to_string();
+    if dto.name.is_empty() || dto.name.contains(['=', ';']) {
+        return Err(format!("Invalid cookie name: '{}'", dto.name));
+    }
+    if dto.value.contains(';') || HeaderValue::from_str(&dto.value).is_err() {
+        return Err(format!("Invalid value for cookie {}", dto.name));
+    }
+    dto.domain = dto
+        .domain
+        .trim()
+        .trim_start_matches('.')
+        .to_ascii_lowercase();
+    if dto.domain.is_empty() {
+        return Err("Cookie domain must not be empty".to_string());
+    }
+    if let Some(path) = dto.path.as_deref().filter(|path| !path.starts_with('/')) {
+        return Err(format!("Cookie path must start with '/': '{}'", path));
+    }
+    dto.expires_at = dto
+        .expires_at
+        .filter(|expires_at| !expires_at.trim().is_empty())
+        .map(|expires_at| {
+            DateTime::parse_from_rfc3339(expires_at.trim())
+                .map(|expires_at| timestamp(expires_at.with_timezone(&Utc)))
+                .map_err(|e| format!("Invalid cookie expiry '{}': {}", expires_at, e))
+        })
+        .transpose()?;
+    dto.same_site = match dto.same_site.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(same_site) => Some(
+            ["Strict", "Lax", "None"]
+                .into_iter()
+                .find(|value| value.eq_ignore_ascii_case(same_site))
+                .ok_or_else(|| format!("Invalid SameSite value: '{}'", same_site))?
+                .to_string(),
+        ),
+    };
+    Ok(dto)
+}
+
+/// Time in the format of `EndpointCookie.expires_at`, which sorts as text.
+pub fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn matches(cookie: &EndpointCookie, url: &Url) -> bool {
+    let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+        return false;
+    };
+    let domain_ok = if cookie.host_only {
+        host == cookie.domain
+    } else {
+        domain_matches(&host, &cookie.domain)
+    };
+    domain_ok && path_matches(url.path(), &cookie.path) && (!cookie.secure || is_secure(url))
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || request_path
+            .strip_prefix(cookie_path)
+            .is_some_and(|rest| cookie_path.ends_with('/') || rest.starts_with('/'))
+}
+
+/// The directory of the request path, used when a cookie sets no path.
+fn default_path(url: &Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(end) => url.path()[..end].to_string(),
+    }
+}
+
+/// Whether `Secure` cookies may be sent to the URL. Like browsers, local
+/// servers count as secure so development setups over plain HTTP work.
+fn is_secure(url: &Url) -> bool {
+    if matches!(url.scheme(), "https" | "wss") {
+        return true;
+    }
+    match url.domain() {
+        Some(host) => {
+            let host = host.to_ascii_lowercase();
+            host == "localhost" || host.ends_with(".localhost")
+        }
+        None => url
+            .host_str()
+            .and_then(|host| host.trim_matches(['[', ']']).parse::<IpAddr>().ok())
+            .is_some_and(|ip| ip.is_loopback()),
+    }
+}
@@ -0,0 +1,412 @@
+use log::debug;
+use md5::Md5;
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use reqwest::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use reqwest::{Request, Response, StatusCode, Url};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Digest challenges received so far, keyed by origin and user, so later
+/// requests authenticate up front and reuse the server's nonce.
+static SESSIONS: Lazy<Mutex<HashMap<String, DigestSession>>> = Lazy::new(Default::default);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DigestAlgorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+impl DigestAlgorithm {
+    fn parse(name: Option<&str>) -> Option<Self> {
+        match name.map(str::to_ascii_uppercase).as_deref() {
+            None | Some("MD5") => Some(Self::Md5),
+            Some("MD5-SESS") => Some(Self::Md5Sess),
+            Some("SHA-256") => Some(Self::Sha256),
+            Some("SHA-256-SESS") => Some(Self::Sha256Sess),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Md5 => "MD5",
+            Self::Md5Sess => "MD5-sess",
+            Self::Sha256 => "SHA-256",
+            Self::Sha256Sess => "SHA-256-sess",
+        }
+    }
+
+    fn is_session(self) -> bool {
+        matches!(self, Self::Md5Sess | Self::Sha256Sess)
+    }
+
+    fn hash(self, data: &str) -> String {
+        let digest = match self {
+            Self::Md5 | Self::Md5Sess => Md5::digest(data.as_bytes()).to_vec(),
+            Self::Sha256 | Self::Sha256Sess => Sha256::digest(data.as_bytes()).to_vec(),
+        };
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// A Digest challenge from a `WWW-Authenticate` header (RFC 7616)
+#[derive(Debug, Clone)]
+struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: DigestAlgorithm,
+    /// Whether the server offered `qop=auth`; RFC 2069 servers offer none
+    qop_auth: bool,
+    stale: bool,
+}
+
+impl DigestChallenge {
+    /// The strongest supported Digest challenge among the headers.
+    fn from_response(response: &Response) -> Option<Self> {
+        response
+            .headers()
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(parse_challenges)
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("digest"))
+            .filter_map(|(_, params)| Self::from_params(params))
+            .max_by_key(|challenge| {
+                matches!(
+                    challenge.algorithm,
+                    DigestAlgorithm::Sha256 | DigestAlgorithm::Sha256Sess
+                )
+            })
+    }
+
+    fn from_params(mut params: HashMap<String, String>) -> Option<Self> {
+        let algorithm = DigestAlgorithm::parse(params.get("algorithm").map(String::as_str))?;
+        let qop = params.remove("qop");
+        let qop_auth = qop.as_deref().is_some_and(|qop| {
+            qop.split(',')
+                .any(|q| q.trim().eq_ignore_ascii_case("auth"))
+        });
+        // Servers that only accept `auth-int` are not supported.
+        if qop.is_some() && !qop_auth {
+            return None;
+        }
+        Some(Self {
+            realm: params.remove("realm").unwrap_or_default(),
+            nonce: params.remove("nonce")?,
+            opaque: params.remove("opaque"),
+            algorithm,
+            qop_auth,
+            stale: params
+                .get("stale")
+                .is_some_and(|stale| stale.eq_ignore_ascii_case("true")),
+        })
+    }
+}
+
+struct DigestSession {
+    challenge: DigestChallenge,
+    /// Requests sent with the current nonce
+    nonce_count: u32,
+}
+
+/// Username and password answering Digest challenges
+#[derive(Debug, Clone)]
+pub struct DigestCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl DigestCredentials {
+    /// Adds the `Authorization` header when a challenge of the request's
+    /// origin is known, counting the use of its nonce.
+    pub fn authorize(&self, request: &mut Request) -> Result<(), String> {
+        let key = self.session_key(request.url());
+        let mut sessions = SESSIONS.lock().expect("digest sessions poisoned");
+        let Some(session) = sessions.get_mut(&key) else {
+            return Ok(());
+        };
+        session.nonce_count += 1;
+        let mut cnonce = [0u8; 16];
+        rand::rng().fill_bytes(&mut cnonce);
+        let cnonce: String = cnonce.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let authorization = self.authorization(
+            &session.challenge,
+            session.nonce_count,
+            &cnonce,
+            request.method().as_str(),
+            request.url(),
+        );
+        let value = HeaderValue::from_str(&authorization)
+            .map_err(|e| format!("Invalid Digest credentials: {}", e))?;
+        request.headers_mut().insert(AUTHORIZATION, value);
+        Ok(())
+    }
+
+    /// Takes the Digest challenge of a 401 response to `url`. Returns
+    /// whether the request should be sent again: when the challenge is new
+    /// or the nonce went stale, but not when the credentials were rejected.
+    pub fn accept_challenge(&self, url: &Url, response: &Response) -> bool {
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return false;
+        }
+        let Some(challenge) = DigestChallenge::from_response(response) else {
+            return false;
+        };
+
+        let key = self.session_key(url);
+        let mut sessions = SESSIONS.lock().expect("digest sessions poisoned");
+        let rejected = sessions
+            .get(&key)
+            .is_some_and(|session| session.challenge.nonce == challenge.nonce && !challenge.stale);
+        if rejected {
+            debug!("Digest credentials rejected by {}", url);
+            sessions.remove(&key);
+            return false;
+        }
+        sessions.insert(
+            key,
+            DigestSession {
+                challenge,
+                nonce_count: 0,
+            },
+        );
+        true
+    }
+
+    fn session_key(&self, url: &Url) -> String {
+        format!("{} {}", url.origin().ascii_serialization(), self.username)
+    }
+
+    fn authorization(
+        &self,
+        challenge: &DigestChallenge,
+        nonce_count: u32,
+        cnonce: &str,
+        method: &str,
+        url: &Url,
+    ) -> String {
+        let algorithm = challenge.algorithm;
+        let uri = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let nc = format!("{:08x}", nonce_count);
+
+        let mut ha1 = algorithm.hash(&format!(
+            "{}:{}:{}",
+            self.username, challenge.realm, self.password
+        ));
+        if algorithm.is_session() {
+            ha1 = algorithm.hash(&format!("{}:{}:{}", ha1, challenge.nonce, cnonce));
+        }
+        let ha2 = algorithm.hash(&format!("{}:{}", method, uri));
+        let response = if challenge.qop_auth {
+            algorithm.hash(&format!(
+                "{}:{}:{}:{}:auth:{}",
+                ha1, challenge.nonce, nc, cnonce, ha2
+            ))
+        } else {
+            algorithm.hash(&format!("{}:{}:{}", ha1, challenge.nonce, ha2))
+        };
+
+        let mut header = format!(
+            r#"Digest username="{}", realm="{}", nonce="{}", uri="{}", algorithm={}, response="{}""#,
+            quote(&self.username),
+            quote(&challenge.realm),
+            quote(&challenge.nonce),
+            quote(&uri),
+            algorithm.name(),
+            response
+        );
+        if challenge.qop_auth {
+            header.push_str(&format!(r#", qop=auth, nc={}, cnonce="{}""#, nc, cnonce));
+        }
+        if let Some(opaque) = &challenge.opaque {
+            header.push_str(&format!(r#", opaque="{}""#, quote(opaque)));
+        }
+        header
+    }
+}
+
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Splits a `WWW-Authenticate` value into challenges: a scheme followed by
+/// comma-separated `name=value` parameters, values possibly quoted.
+fn parse_challenges(header: &str) -> Vec<(String, HashMap<String, String>)> {
+    let mut challenges: Vec<(String, HashMap<String, String>)> = Vec::new();
+    let mut rest = header.trim_start();
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| c == '=' || c == ',' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let token = &rest[..end];
+        let after = rest[end..].trim_start();
+
+        match after.strip_prefix('=') {
+            Some(value) if !token.is_empty() => {
+                let (value, remaining) = parse_value(value.trim_start());
+                if let Some((_, params)) = challenges.last_mut() {
+                    params.insert(token.to_ascii_lowercase(), value);
+                }
+                rest = remaining;
+            }
+            _ if !token.is_empty() => {
+                challenges.push((token.to_string(), HashMap::new()));
+                rest = after;
+            }
+            // Stray separator
+            _ => rest = &after[1.min(after.len())..],
+        }
+        rest = rest.trim_start_matches([',', ' ', '\t']);
+    }
+    challenges
+}
+
+/// Parses a token or quoted string, returning it and the remaining input.
+fn parse_value(input: &str) -> (String, &str) {
+    let Some(quoted) = input.strip_prefix('"') else {
+        let end = input.find(',').unwrap_or(input.len());
+        return (input[..end].trim().to_string(), &input[end..]);
+    };
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, escaped)) = chars.next() {
+                    value.push(escaped);
+                }
+            }
+            '"' => return (value, &quoted[i + 1..]),
+            c => value.push(c),
+        }
+    }
+    (value, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The challenge of the examples in RFC 7616, section 3.9.1
+    const RFC_CHALLENGE: &str = r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=SHA-256, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS", Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=MD5, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#;
+    const RFC_CNONCE: &str = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+    fn credentials(username: &str) -> DigestCredentials {
+        DigestCredentials {
+            username: username.to_string(),
+            password: "Circle of Life".to_string(),
+        }
+    }
+
+    fn unauthorized(challenge: &str) -> Response {
+        hyper::Response::builder()
+            .status(401)
+            .header(WWW_AUTHENTICATE, challenge)
+            .body("")
+            .unwrap()
+            .into()
+    }
+
+    fn rfc_challenge(algorithm: DigestAlgorithm) -> DigestChallenge {
+        parse_challenges(RFC_CHALLENGE)
+            .into_iter()
+            .filter_map(|(_, params)| DigestChallenge::from_params(params))
+            .find(|challenge| challenge.algorithm == algorithm)
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_the_examples_of_rfc_7616() {
+        let url = Url::parse("http://www.example.org/dir/index.html").unwrap();
+        let credentials = credentials("Mufasa");
+        for (algorithm, response) in [
+            (DigestAlgorithm::Md5, "8ca523f5e9506fed4657c9700eebdbec"),
+            (
+                DigestAlgorithm::Sha256,
+                "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1",
+            ),
+        ] {
+            let challenge = rfc_challenge(algorithm);
+            let header = credentials.authorization(&challenge, 1, RFC_CNONCE, "GET", &url);
+            assert_eq!(
+                header,
+                format!(
+                    r#"Digest username="Mufasa", realm="http-auth@example.org", nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", uri="/dir/index.html", algorithm={}, response="{}", qop=auth, nc=00000001, cnonce="{}", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#,
+                    algorithm.name(),
+                    response,
+                    RFC_CNONCE
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn prefers_sha_256_among_several_challenges() {
+        let challenge = DigestChallenge::from_response(&unauthorized(RFC_CHALLENGE)).unwrap();
+        assert_eq!(challenge.algorithm, DigestAlgorithm::Sha256);
+        assert!(challenge.qop_auth);
+    }
+
+    #[test]
+    fn parses_several_challenges_with_quoted_commas() {
+        let challenges = parse_challenges(
+            r#"Basic realm="a, b", Digest realm="say \"hi\", then go",nonce=abc, qop="auth,auth-int""#,
+        );
+        assert_eq!(challenges.len(), 2);
+        let (scheme, params) = &challenges[0];
+        assert_eq!(scheme, "Basic");
+        assert_eq!(params.len(), 1);
+        assert_eq!(params["realm"], "a, b");
+        let (scheme, params) = &challenges[1];
+        assert_eq!(scheme, "Digest");
+        assert_eq!(params.len(), 3);
+        assert_eq!(params["realm"], r#"say "hi", then go"#);
+        assert_eq!(params["nonce"], "abc");
+        assert_eq!(params["qop"], "auth,auth-int");
+    }
+
+    #[test]
+    fn retries_new_and_stale_nonces_but_not_rejected_credentials() {
+        let url = Url::parse("http://127.0.0.1:9/digest").unwrap();
+        let credentials = credentials("stale-and-rejected");
+        let challenge = |nonce: &str, stale: bool| {
+            unauthorized(&format!(
+                r#"Digest realm="r", qop="auth", nonce="{}", stale={}"#,
+                nonce, stale
+            ))
+        };
+        let authorization = || {
+            let mut request = Request::new(reqwest::Method::GET, url.clone());
+            credentials.authorize(&mut request).unwrap();
+            request
+                .headers()
+                .get(AUTHORIZATION)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+
+        assert_eq!(authorization(), None);
+        assert!(credentials.accept_challenge(&url, &challenge("n1", false)));
+        assert!(authorization().unwrap().contains("nc=00000001"));
+        assert!(authorization().unwrap().contains("nc=00000002"));
+
+        // A stale nonce is replaced and counted from the start again.
+        assert!(credentials.accept_challenge(&url, &challenge("n2", true)));
+        let header = authorization().unwrap();
+        assert!(header.contains(r#"nonce="n2""#) && header.contains("nc=00000001"));
+
+        // The same nonce challenged again means the password is wrong.
+        assert!(!credentials.accept_challenge(&url, &challenge("n2", false)));
+        assert_eq!(authorization(), None);
+
+        let ok = hyper::Response::builder().body("").unwrap().into();
+        assert!(!credentials.accept_challenge(&url, &ok));
+    }
+}
@@ -0,0 +1,310 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::debug;
+use reqwest::Url;
+use serde_json::Value;
+use sqlx::SqlitePool;
+use std::future::Future;
+
+use crate::common::cookie_jar::CookieJar;
+use crate::common::graphql_upload::PREFLIGHT_HEADER;
+use crate::common::http_client::RequestOptions;
+use crate::common::oauth2::OAuthTokenCache;
+use crate::common::proxy_http::{HttpError, HttpHeader, HttpRequest, HttpResponse};
+use crate::common::request_signing::RequestSigning;
+use crate::common::secret_vault;
+use crate::database::entities::endpoint_entity::{AuthConfig, AuthType, Endpoint};
+use crate::database::repositories::EndpointRepository;
+
+/// Reads a stored header collection into name/value pairs.
+///
+/// The UI persists headers as a JSON string holding an array of
+/// `{ key, value }` rows, while older rows may hold the array or a plain
+/// object directly, so all three shapes are accepted. Rows without a key
+/// are skipped.
+pub fn header_pairs(value: &Value) -> Vec<(String, String)> {
+    match value {
+        Value::String(raw) => serde_json::from_str::<Value>(raw)
+            .map(|parsed| match parsed {
+                Value::String(_) => Vec::new(),
+                parsed => header_pairs(&parsed),
+            })
+            .unwrap_or_default(),
+        Value::Array(rows) => rows
+            .iter()
+            .filter_map(|row| {
+                let key = row.get("key")?.as_str()?.trim();
+                let value = row.get("value").map(value_to_string).unwrap_or_default();
+                (!key.is_empty()).then(|| (key.to_string(), value))
+            })
+            .collect(),
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| (key.clone(), value_to_string(value)))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Headers produced by the endpoint's authentication configuration.
+pub fn auth_headers(auth: &AuthConfig) -> Vec<(String, String)> {
+    let mut headers = Vec::new();
+
+    match auth.auth_type {
+        AuthType::None => {}
+        AuthType::Basic => {
+            let credentials = format!(
+                "{}:{}",
+                auth.username.as_deref().unwrap_or_default(),
+                auth.password.as_deref().unwrap_or_default()
+            );
+            headers.push((
+                "Authorization".to_string(),
+                format!("Basic {}", STANDARD.encode(credentials)),
+            ));
+        }
+        AuthType::Bearer | AuthType::OAuth2 => {
+            if let Some(token) = auth.token.as_deref().filter(|t| !t.is_empty()) {
+                headers.push(("Authorization".to_string(), format!("Bearer {}", token)));
+            }
+        }
+        AuthType::ApiKey => {
+            if let Some((name, value)) = api_key(auth, "header") {
+                headers.push((name.to_string(), value.to_string()));
+            }
+        }
+        AuthType::Custom => {
+            if let Some(custom_headers) = &auth.custom_headers {
+                headers.extend(header_pairs(custom_headers));
+            }
+        }
+        // Signed when the request is sent, see `RequestSigning`.
+        AuthType::Digest | AuthType::AwsSigV4 | AuthType::Hmac => {}
+    }
+
+    headers
+}
+
+/// The API key name and value when it is configured to be sent in
+/// `location` (`header` unless `api_key_in` says otherwise).
+fn api_key<'a>(auth: &'a AuthConfig, location: &str) -> Option<(&'a str, &'a str)> {
+    if auth.auth_type != AuthType::ApiKey {
+        return None;
+    }
+    let configured = auth.api_key_in.as_deref().unwrap_or("header");
+    if !configured.trim().eq_ignore_ascii_case(location) {
+        return None;
+    }
+    let name = auth
+        .api_key_name
+        .as_deref()
+        .filter(|name| !name.is_empty())?;
+    Some((name, auth.api_key_value.as_deref().unwrap_or_default()))
+}
+
+/// Places an API key configured for the query string or a cookie. A
+/// parameter or cookie of the same name already present is kept.
+pub fn apply_api_key_placement(
+    auth: &AuthConfig,
+    url: &mut String,
+    headers: &mut Vec<HttpHeader>,
+) -> Result<(), String> {
+    if let Some((name, value)) = api_key(auth, "query") {
+        let mut parsed = Url::parse(url).map_err(|e| format!("Invalid endpoint URL: {}", e))?;
+        if !parsed.query_pairs().any(|(key, _)| key == name) {
+            parsed.query_pairs_mut().append_pair(name, value);
+            *url = parsed.into();
+        }
+    }
+
+    if let Some((name, value)) = api_key(auth, "cookie") {
+        let existing = headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case("cookie"))
+            .map(|header| header.value.clone());
+        let cookie = format!("{}={}", name, value);
+        match existing {
+            Some(cookies)
+                if cookies
+                    .split(';')
+                    .any(|pair| pair.trim().split('=').next() == Some(name)) => {}
+            Some(cookies) if !cookies.trim().is_empty() => insert_header(
+                headers,
+                "Cookie".to_string(),
+                format!("{}; {}", cookies, cookie),
+            ),
+            _ => insert_header(headers, "Cookie".to_string(), cookie),
+        }
+    }
+
+    Ok(())
+}
+
+/// Inserts a header, replacing every existing entry of the same name,
+/// compared case-insensitively, so the request does not carry both.
+pub fn insert_header(headers: &mut Vec<HttpHeader>, name: String, value: String) {
+    headers.retain(|existing| !existing.name.eq_ignore_ascii_case(&name));
+    headers.push(HttpHeader { name, value });
+}
+
+/// Adds `overrides` to `headers`, replacing the entries of the names they
+/// set. Repeated names in `overrides` are all kept.
+pub fn merge_headers(headers: &mut Vec<HttpHeader>, overrides: Vec<HttpHeader>) {
+    headers.retain(|header| {
+        !overrides
+            .iter()
+            .any(|other| other.name.eq_ignore_ascii_case(&header.name))
+    });
+    headers.extend(overrides);
+}
+
+/// Collects every header configured on an endpoint, later sources
+/// overriding earlier ones: `Endpoint.headers`, then
+/// `GraphQLConfig.default_headers` and the preflight header, then the
+/// authentication headers.
+pub fn endpoint_headers(endpoint: &Endpoint) -> Vec<HttpHeader> {
+    let graphql = endpoint
+        .config
+        .as_ref()
+        .and_then(|config| config.graphql.as_ref());
+    let default_headers = graphql.and_then(|graphql| graphql.default_headers.as_ref());
+    let preflight = graphql
+        .filter(|graphql| graphql.require_preflight.unwrap_or(false))
+        .map(|_| (PREFLIGHT_HEADER.to_string(), "true".to_string()));
+
+    let pairs = endpoint
+        .headers
+        .iter()
+        .chain(default_headers)
+        .flat_map(|headers| header_pairs(headers))
+        .chain(preflight)
+        .chain(endpoint.auth.iter().flat_map(auth_headers));
+
+    let mut headers = Vec::new();
+    for (name, value) in pairs {
+        insert_header(&mut headers, name, value);
+    }
+    headers
+}
+
+/// Network options configured on an endpoint, applied to requests that do
+/// not override them.
+pub fn endpoint_request_options(endpoint: &Endpoint) -> Option<RequestOptions> {
+    endpoint
+        .config
+        .as_ref()
+        .and_then(|config| config.request_options.clone())
+}
+
+/// Applies an endpoint's configuration to a request. Headers are merged in
+/// increasing order of precedence:
+///
+/// 1. `Endpoint.headers`
+/// 2. `GraphQLConfig.default_headers`
+/// 3. headers derived from `AuthConfig`
+/// 4. the request's own headers
+///
+/// API keys with `api_key_in` set to `query` or `cookie` are then added to
+/// the URL or the `Cookie` header, unless the request already sets them,
+/// and request options left unset are taken from
+/// `EndpointConfig.request_options`. Signing auth types set
+/// `request.signing`. Fails while the endpoint's secrets are locked.
+pub fn apply_endpoint(request: &mut HttpRequest, endpoint: &Endpoint) -> Result<(), String> {
+    secret_vault::ensure_unlocked(endpoint)?;
+    let mut headers = endpoint_headers(endpoint);
+    merge_headers(&mut headers, request.headers.take().unwrap_or_default());
+    if let Some(auth) = &endpoint.auth {
+        apply_api_key_placement(auth, &mut request.url, &mut headers)?;
+        request.signing = RequestSigning::from_auth(auth)?;
+    }
+    request.headers = Some(headers);
+
+    if let Some(defaults) = endpoint_request_options(endpoint) {
+        request.options = Some(request.options.take().unwrap_or_default().or(&defaults));
+    }
+    Ok(())
+}
+
+/// Loads the endpoint referenced by `request.endpoint_id`, if any.
+async fn request_endpoint(
+    pool: &SqlitePool,
+    request: &HttpRequest,
+) -> Result<Option<Endpoint>, HttpError> {
+    let Some(endpoint_id) = &request.endpoint_id else {
+        return Ok(None);
+    };
+    EndpointRepository::find_by_id(pool, endpoint_id)
+        .await
+        .map_err(|e| HttpError::new(format!("Failed to load endpoint: {}", e)))?
+        .ok_or_else(|| HttpError::new(format!("Endpoint not found: {}", endpoint_id)))
+        .map(Some)
+}
+
+/// Applies the endpoint with an up-to-date OAuth2 token and attaches its
+/// cookie jar.
+async fn apply_authorized_endpoint(
+    pool: &SqlitePool,
+    tokens: &OAuthTokenCache,
+    request: &mut HttpRequest,
+    endpoint: &Endpoint,
+) -> Result<(), HttpError> {
+    let endpoint = tokens
+        .authorize(pool, endpoint)
+        .await
+        .map_err(HttpError::new)?;
+    apply_endpoint(request, &endpoint).map_err(HttpError::new)?;
+    request.cookie_jar = Some(CookieJar::new(pool.clone(), endpoint.id.to_string()));
+    Ok(())
+}
+
+/// Loads the endpoint referenced by `request.endpoint_id`, if any, and
+/// applies its configuration with `apply_endpoint`.
+pub async fn apply_endpoint_by_id(
+    pool: &SqlitePool,
+    tokens: &OAuthTokenCache,
+    request: &mut HttpRequest,
+) -> Result<(), HttpError> {
+    if let Some(endpoint) = request_endpoint(pool, request).await? {
+        apply_authorized_endpoint(pool, tokens, request, &endpoint).await?;
+    }
+    Ok(())
+}
+
+/// Sends a request through `send` with the configuration of its endpoint
+/// applied. When the endpoint's token is obtained by the backend, through
+/// OAuth2 or a token script, and the server answers 401, the token is
+/// renewed and the request retried once.
+pub async fn send_with_endpoint<F, Fut>(
+    pool: &SqlitePool,
+    tokens: &OAuthTokenCache,
+    request: HttpRequest,
+    send: F,
+) -> Result<HttpResponse, HttpError>
+where
+    F: Fn(HttpRequest) -> Fut,
+    Fut: Future<Output = Result<HttpResponse, HttpError>>,
+{
+    let Some(endpoint) = request_endpoint(pool, &request).await? else {
+        return send(request).await;
+    };
+
+    let mut prepared = request.clone();
+    apply_authorized_endpoint(pool, tokens, &mut prepared, &endpoint).await?;
+    let response = send(prepared).await?;
+    if response.status_code != 401 || !OAuthTokenCache::manages(&endpoint) {
+        return Ok(response);
+    }
+
+    debug!("Token rejected by {}, retrying with a new one", request.url);
+    tokens.invalidate(&endpoint.id.to_string()).await;
+    let mut retry = request;
+    apply_authorized_endpoint(pool, tokens, &mut retry, &endpoint).await?;
+    send(retry).await
+}
@@ -0,0 +1,315 @@
+use serde::Serialize;
+use strum_macros::Display;
+use typeshare::typeshare;
+
+/// Kind of an executable GraphQL operation
+#[typeshare]
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum OperationType {
+    Query,
+    Mutation,
+    Subscription,
+}
+
+/// An operation definition found in a GraphQL document
+#[typeshare]
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphQLOperation {
+    /// Kind of the operation (query, mutation or subscription)
+    pub operation_type: OperationType,
+    /// Operation name, `None` for anonymous operations
+    pub name: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Name(&'a str),
+    Punctuator(char),
+    Value,
+}
+
+/// Splits a GraphQL document into the tokens needed to locate operation
+/// definitions. String and number literals are collapsed into `Token::Value`,
+/// comments and insignificant commas are skipped.
+fn tokenize(document: &str) -> Result<Vec<Token<'_>>, String> {
+    let bytes = document.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        match c {
+            b' ' | b'\t' | b'\n' | b'\r' | b',' => i += 1,
+            // Unicode BOM
+            0xEF if bytes[i..].starts_with(&[0xEF, 0xBB, 0xBF]) => i += 3,
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' && bytes[i] != b'\r' {
+                    i += 1;
+                }
+            }
+            b'"' if bytes[i..].starts_with(b"\"\"\"") => {
+                i += 3;
+                loop {
+                    if i >= bytes.len() {
+                        return Err("Unterminated block string".to_string());
+                    }
+                    if bytes[i..].starts_with(b"\\\"\"\"") {
+                        i += 4;
+                    } else if bytes[i..].starts_with(b"\"\"\"") {
+                        i += 3;
+                        break;
+                    } else {
+                        i += 1;
+                    }
+                }
+                tokens.push(Token::Value);
+            }
+            b'"' => {
+                i += 1;
+                loop {
+                    match bytes.get(i) {
+                        None | Some(b'\n') | Some(b'\r') => {
+                            return Err("Unterminated string".to_string());
+                        }
+                        Some(b'\\') => i += 2,
+                        Some(b'"') => {
+                            i += 1;
+                            break;
+                        }
+                        Some(_) => i += 1,
+                    }
+                }
+                tokens.push(Token::Value);
+            }
+            b'.' if bytes[i..].starts_with(b"...") => {
+                tokens.push(Token::Punctuator('.'));
+                i += 3;
+            }
+            b'!' | b'$' | b'&' | b'(' | b')' | b':' | b'=' | b'@' | b'[' | b']' | b'{' | b'|'
+            | b'}' => {
+                tokens.push(Token::Punctuator(c as char));
+                i += 1;
+            }
+            b'-' | b'0'..=b'9' => {
+                i += 1;
+                while i < bytes.len()
+                    && (bytes[i].is_ascii_alphanumeric() || matches!(bytes[i], b'.' | b'+' | b'-'))
+                {
+                    i += 1;
+                }
+                tokens.push(Token::Value);
+            }
+            b'_' | b'A'..=b'Z' | b'a'..=b'z' => {
+                let start = i;
+                while i < bytes.len() && (bytes[i] == b'_' || bytes[i].is_ascii_alphanumeric()) {
+                    i += 1;
+                }
+                tokens.push(Token::Name(&document[start..i]));
+            }
+            _ => {
+                let ch = document[i..].chars().next().unwrap_or_default();
+                return Err(format!("Unexpected character '{}'", ch));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Lists the operation definitions of a GraphQL document in source order.
+///
+/// This is intentionally not a full GraphQL parser: it only tracks nesting
+/// depth to find top-level definitions and reads their keyword and name.
+/// Fragment and type-system definitions are skipped.
+pub fn parse_operations(document: &str) -> Result<Vec<GraphQLOperation>, String> {
+    let tokens = tokenize(document)?;
+    let mut operations = Vec::new();
+    let mut depth: usize = 0;
+    let mut expecting_definition = true;
+    let mut iter = tokens.iter().peekable();
+
+    while let Some(token) = iter.next() {
+        match token {
+            Token::Punctuator('{') if depth == 0 && expecting_definition => {
+                // Query shorthand: `{ field }`
+                operations.push(GraphQLOperation {
+                    operation_type: OperationType::Query,
+                    name: None,
+                });
+                expecting_definition = false;
+                depth += 1;
+            }
+            Token::Name(keyword) if depth == 0 && expecting_definition => {
+                expecting_definition = false;
+                let operation_type = match *keyword {
+                    "query" => OperationType::Query,
+                    "mutation" => OperationType::Mutation,
+                    "subscription" => OperationType::Subscription,
+                    _ => continue,
+                };
+                let name = match iter.peek() {
+                    Some(Token::Name(name)) => {
+                        iter.next();
+                        Some(name.to_string())
+                    }
+                    _ => None,
+                };
+                operations.push(GraphQLOperation {
+                    operation_type,
+                    name,
+                });
+            }
+            Token::Punctuator('{' | '(' | '[') => depth += 1,
+            Token::Punctuator(closing @ ('}' | ')' | ']')) => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| format!("Unexpected '{}'", closing))?;
+                if depth == 0 && *closing == '}' {
+                    expecting_definition = true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if depth != 0 {
+        return Err("Unexpected end of document".to_string());
+    }
+
+    Ok(operations)
+}
+
+/// Whether the document uses `@defer` or `@stream`, whose results may be
+/// delivered incrementally.
+pub fn uses_incremental_delivery(document: &str) -> Result<bool, String> {
+    let tokens = tokenize(document)?;
+    Ok(tokens.windows(2).any(|pair| {
+        matches!(
+            pair,
+            [Token::Punctuator('@'), Token::Name("defer" | "stream")]
+        )
+    }))
+}
+
+/// Selects the operation a request will execute: the one matching
+/// `operation_name`, or the only operation of the document. Documents with
+/// several operations require an explicit name.
+pub fn select_operation<'a>(
+    operations: &'a [GraphQLOperation],
+    operation_name: Option<&str>,
+) -> Result<&'a GraphQLOperation, String> {
+    match (operation_name, operations) {
+        (Some(name), _) => operations
+            .iter()
+            .find(|operation| operation.name.as_deref() == Some(name))
+            .ok_or_else(|| format!("Unknown operation named \"{}\"", name)),
+        (None, [operation]) => Ok(operation),
+        (None, []) => Err("Document does not contain any operation".to_string()),
+        (None, _) => Err(
+            "Document contains multiple operations, an operation name must be provided".to_string(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(document: &str) -> Vec<(OperationType, Option<String>)> {
+        parse_operations(document)
+            .unwrap()
+            .into_iter()
+            .map(|operation| (operation.operation_type, operation.name))
+            .collect()
+    }
+
+    #[test]
+    fn ignores_braces_and_keywords_in_strings_and_comments() {
+        let document = r#"
+            # mutation Hidden { @defer }
+            query Search($text: String = "mutation { x @defer }") {
+              search(text: $text, hint: """
+                a block string with { braces }, mutation Fake { y }
+                and an escaped \""" quote @stream
+              """) {
+                id # } closing brace in a comment
+              }
+            }
+        "#;
+        assert_eq!(
+            summary(document),
+            vec![(OperationType::Query, Some("Search".to_string()))]
+        );
+        assert!(!uses_incremental_delivery(document).unwrap());
+    }
+
+    #[test]
+    fn finds_anonymous_operations() {
+        assert_eq!(summary("{ a }"), vec![(OperationType::Query, None)]);
+        assert_eq!(
+            summary("mutation { like(id: 1) { count } }"),
+            vec![(OperationType::Mutation, None)]
+        );
+        assert_eq!(
+            summary("subscription($id: ID!) { ticks(id: $id) }"),
+            vec![(OperationType::Subscription, None)]
+        );
+    }
+
+    #[test]
+    fn requires_a_name_with_several_unnamed_operations() {
+        let operations = parse_operations("{ a } query { b }").unwrap();
+        assert_eq!(operations.len(), 2);
+        assert!(select_operation(&operations, None)
+            .unwrap_err()
+            .contains("multiple operations"));
+        assert!(select_operation(&operations, Some("A"))
+            .unwrap_err()
+            .contains("Unknown operation"));
+    }
+
+    #[test]
+    fn selects_operations_by_name() {
+        let operations =
+            parse_operations("query A { a } mutation B { b } subscription C { c }").unwrap();
+        let selected = select_operation(&operations, Some("B")).unwrap();
+        assert_eq!(selected.operation_type, OperationType::Mutation);
+        assert!(select_operation(&[], None)
+            .unwrap_err()
+            .contains("does not contain"));
+    }
+
+    #[test]
+    fn skips_fragments() {
+        let document = r#"
+            fragment Fields on User { id friends { ...Names } }
+            query Me { me { ...Fields ... on Admin { level } } }
+            fragment Names on User { name }
+        "#;
+        assert_eq!(
+            summary(document),
+            vec![(OperationType::Query, Some("Me".to_string()))]
+        );
+    }
+
+    #[test]
+    fn detects_defer_and_stream_directives() {
+        assert!(uses_incremental_delivery("{ me { ...F @defer } }").unwrap());
+        assert!(uses_incremental_delivery("{ list @stream(initialCount: 1) }").unwrap());
+        assert!(
+            uses_incremental_delivery("fragment F on T { a } { b ...F @defer(label: \"x\") }")
+                .unwrap()
+        );
+        assert!(!uses_incremental_delivery("{ defer stream }").unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        assert!(parse_operations("query { a").is_err());
+        assert!(parse_operations("{ a } }").is_err());
+        assert!(parse_operations("{ a(text: \"open) }").is_err());
+        assert!(parse_operations("{ a(text: \"\"\"open) }").is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::debug;
+use serde::Serialize;
+use serde_json::{json, Value};
+use typeshare::typeshare;
+
+use crate::common::endpoint_request::apply_endpoint;
+use crate::common::proxy_http::{proxy_http, HttpHeader, HttpRequest, HttpResponse};
+use crate::common::request_body::HttpBody;
+use crate::common::response_body::BodyEncoding;
+use crate::database::entities::endpoint_entity::Endpoint;
+
+/// The standard introspection query, matching `getIntrospectionQuery()`
+/// from graphql-js with its default options.
+pub const INTROSPECTION_QUERY: &str = r#"
+query IntrospectionQuery {
+  __schema {
+    queryType { name }
+    mutationType { name }
+    subscriptionType { name }
+    types {
+      ...FullType
+    }
+    directives {
+      name
+      description
+      locations
+      args {
+        ...InputValue
+      }
+    }
+  }
+}
+
+fragment FullType on __Type {
+  kind
+  name
+  description
+  fields(includeDeprecated: true) {
+    name
+    description
+    args {
+      ...InputValue
+    }
+    type {
+      ...TypeRef
+    }
+    isDeprecated
+    deprecationReason
+  }
+  inputFields {
+    ...InputValue
+  }
+  interfaces {
+    ...TypeRef
+  }
+  enumValues(includeDeprecated: true) {
+    name
+    description
+    isDeprecated
+    deprecationReason
+  }
+  possibleTypes {
+    ...TypeRef
+  }
+}
+
+fragment InputValue on __InputValue {
+  name
+  description
+  type { ...TypeRef }
+  defaultValue
+}
+
+fragment TypeRef on __Type {
+  kind
+  name
+  ofType {
+    kind
+    name
+    ofType {
+      kind
+      name
+      ofType {
+        kind
+        name
+        ofType {
+          kind
+          name
+          ofType {
+            kind
+            name
+            ofType {
+              kind
+              name
+              ofType {
+                kind
+                name
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+}
+"#;
+
+/// Result of an introspection, either fresh or read from the schema cache
+#[typeshare]
+#[derive(Debug, Serialize)]
+pub struct IntrospectedSchema {
+    /// Identifier of the introspected endpoint
+    pub endpoint_id: String,
+    /// JSON string of the introspection result (the `data` object)
+    pub schema: String,
+    /// RFC 3339 timestamp of when the schema was fetched from the server
+    pub fetched_at: String,
+    /// Whether the schema was served from the endpoint's schema cache
+    pub from_cache: bool,
+}
+
+/// Runs the introspection query against an endpoint, using its configured
+/// headers and authentication, and returns the validated `data` object as
+/// a JSON string.
+pub async fn fetch_introspection(endpoint: &Endpoint) -> Result<String, String> {
+    let body = json!({
+        "query": INTROSPECTION_QUERY,
+        "operationName": "IntrospectionQuery",
+    });
+
+    let mut request = HttpRequest {
+        method: "POST".to_string(),
+        url: endpoint.url.clone(),
+        headers: Some(vec![
+            HttpHeader::new("Content-Type", "application/json"),
+            HttpHeader::new("Accept", "application/json"),
+        ]),
+        body: Some(HttpBody::Json(body.to_string())),
+        options: None,
+        endpoint_id: None,
+        signing: None,
+        cookie_jar: None,
+    };
+    apply_endpoint(&mut request, endpoint)?;
+
+    let response = proxy_http(request).await.map_err(|e| e.message)?;
+    let body = response_text(&response).await?;
+
+    if !(200..300).contains(&response.status_code) {
+        return Err(format!(
+            "Unexpected response status: {} - {}",
+            response.status_code, body
+        ));
+    }
+
+    validate_introspection(&body)
+}
+
+/// The body of a response as text. Large schemas arrive as a temporary
+/// file, which is removed once read.
+async fn response_text(response: &HttpResponse) -> Result<String, String> {
+    match response.body_encoding {
+        BodyEncoding::Text => Ok(response.body.clone()),
+        BodyEncoding::Base64 => STANDARD
+            .decode(&response.body)
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .map_err(|e| format!("Invalid introspection response: {}", e)),
+        BodyEncoding::File => {
+            let text = tokio::fs::read(&response.body).await;
+            if let Err(e) = tokio::fs::remove_file(&response.body).await {
+                debug!("Failed to remove {}: {}", response.body, e);
+            }
+            text.map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                .map_err(|e| format!("Failed to read introspection response: {}", e))
+        }
+    }
+}
+
+/// Checks that a response body is a successful introspection result and
+/// extracts its `data` object.
+fn validate_introspection(body: &str) -> Result<String, String> {
+    let mut response: Value =
+        serde_json::from_str(body).map_err(|e| format!("Invalid introspection response: {}", e))?;
+
+    if let Some(errors) = response
+        .get("errors")
+        .and_then(Value::as_array)
+        .filter(|errors| !errors.is_empty())
+    {
+        let messages: Vec<&str> = errors
+            .iter()
+            .filter_map(|error| error.get("message").and_then(Value::as_str))
+            .collect();
+        return Err(format!(
+            "GraphQL introspection errors: {}",
+            messages.join(", ")
+        ));
+    }
+
+    let data = response
+        .get_mut("data")
+        .map(Value::take)
+        .filter(|data| {
+            data.pointer("/__schema/queryType").is_some()
+                && data.pointer("/__schema/types").is_some_and(Value::is_array)
+        })
+        .ok_or_else(|| "Invalid introspection response: schema data missing".to_string())?;
+
+    Ok(data.to_string())
+}
@@ -0,0 +1,565 @@
+use futures_util::StreamExt;
+use reqwest::Response;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::common::graphql_subscription::SubscriptionEvent;
+use crate::common::http_client::ClientKind;
+use crate::common::proxy_http::{send_http, HttpError, HttpRequest, HttpResponse};
+
+/// `Accept` header value asking for incremental delivery of `@defer` and
+/// `@stream` results, falling back to a single JSON response.
+pub const INCREMENTAL_ACCEPT: &str = "multipart/mixed;deferSpec=20220824, application/json";
+
+/// Incremental parser for `multipart/mixed` bodies. Parts are returned as
+/// soon as the delimiter that ends them has been received.
+pub struct MultipartParser {
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    in_part: bool,
+    finished: bool,
+}
+
+impl MultipartParser {
+    pub fn new(boundary: &str) -> Self {
+        Self {
+            delimiter: format!("--{}", boundary).into_bytes(),
+            buffer: Vec::new(),
+            in_part: false,
+            finished: false,
+        }
+    }
+
+    /// Whether the closing delimiter has been received.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Feeds a chunk of the body and returns the bodies of completed parts.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        let mut parts = Vec::new();
+        if self.finished {
+            return parts;
+        }
+        self.buffer.extend_from_slice(chunk);
+
+        while let Some(pos) = self.find_delimiter() {
+            if self.in_part {
+                let mut end = pos;
+                if end > 0 && self.buffer[end - 1] == b'\n' {
+                    end -= 1;
+                }
+                if end > 0 && self.buffer[end - 1] == b'\r' {
+                    end -= 1;
+                }
+                parts.push(part_body(&self.buffer[..end]).to_vec());
+                self.buffer.drain(..pos);
+                self.in_part = false;
+                continue;
+            }
+
+            // Wait until the delimiter line is complete.
+            let after = pos + self.delimiter.len();
+            if self.buffer.len() < after + 2 {
+                break;
+            }
+            if &self.buffer[after..after + 2] == b"--" {
+                self.finished = true;
+                self.buffer.clear();
+                break;
+            }
+            let Some(line_end) = self.buffer[after..].iter().position(|b| *b == b'\n') else {
+                break;
+            };
+            self.buffer.drain(..after + line_end + 1);
+            self.in_part = true;
+        }
+
+        parts
+    }
+
+    /// Finds a delimiter at the start of a line.
+    fn find_delimiter(&self) -> Option<usize> {
+        self.buffer
+            .windows(self.delimiter.len())
+            .enumerate()
+            .find(|(i, window)| {
+                *window == self.delimiter.as_slice() && (*i == 0 || self.buffer[i - 1] == b'\n')
+            })
+            .map(|(i, _)| i)
+    }
+}
+
+/// Strips the header fields, if any, from a part. Per RFC 2046 they end at
+/// an empty line, which starts the part when it has no headers. Servers that
+/// omit the empty line are tolerated: the body then starts at the first line
+/// that is not a header field.
+fn part_body(part: &[u8]) -> &[u8] {
+    let mut rest = part;
+    let mut in_headers = false;
+    while let Some(line_end) = rest.iter().position(|b| *b == b'\n') {
+        let line = &rest[..line_end];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            return &rest[line_end + 1..];
+        }
+        // Lines starting with whitespace continue a folded header field.
+        let continued = in_headers && matches!(line[0], b' ' | b'\t');
+        if !continued && !is_header_field(line) {
+            break;
+        }
+        in_headers = true;
+        rest = &rest[line_end + 1..];
+    }
+    if in_headers {
+        rest
+    } else {
+        part
+    }
+}
+
+/// Whether a line has the `name: value` form of a header field.
+fn is_header_field(line: &[u8]) -> bool {
+    match line.iter().position(|b| *b == b':') {
+        Some(colon) if colon > 0 => line[..colon]
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(b)),
+        _ => false,
+    }
+}
+
+/// Assembles the final result of an incrementally delivered operation by
+/// applying each subsequent payload at its `path`.
+///
+/// Supports both the `deferSpec=20220824` format (`incremental` entries
+/// carrying a `path`) and the newer format where entries reference a
+/// `pending` id, as well as the early format with `path` at the top level.
+#[derive(Default)]
+pub struct IncrementalMerger {
+    result: Map<String, Value>,
+    pending: HashMap<String, Vec<Value>>,
+}
+
+impl IncrementalMerger {
+    pub fn apply(&mut self, mut payload: Value) {
+        let Some(payload) = payload.as_object_mut() else {
+            return;
+        };
+
+        let completed = take_array(payload, "completed");
+        for pending in take_array(payload, "pending") {
+            if let (Some(id), Some(path)) = (
+                pending.get("id").and_then(Value::as_str),
+                pending.get("path").and_then(Value::as_array),
+            ) {
+                self.pending.insert(id.to_string(), path.clone());
+            }
+        }
+
+        let mut incremental = take_array(payload, "incremental");
+        if payload.contains_key("path") {
+            // Early format: the payload itself is the only incremental entry.
+            incremental.push(Value::Object(std::mem::take(payload)));
+        }
+
+        if self.result.is_empty() {
+            for (key, value) in std::mem::take(payload) {
+                if key != "hasNext" {
+                    self.result.insert(key, value);
+                }
+            }
+        } else {
+            self.extend_errors(take_array(payload, "errors"));
+            if let Some(Value::Object(extensions)) = payload.remove("extensions") {
+                merge_object(&mut self.result, "extensions", extensions);
+            }
+        }
+
+        for entry in incremental {
+            self.apply_entry(entry);
+        }
+
+        for completed in completed {
+            if let Some(id) = completed.get("id").and_then(Value::as_str) {
+                self.pending.remove(id);
+            }
+            if let Some(Value::Array(errors)) = completed.get("errors") {
+                self.extend_errors(errors.clone());
+            }
+        }
+    }
+
+    fn apply_entry(&mut self, mut entry: Value) {
+        let Some(entry) = entry.as_object_mut() else {
+            return;
+        };
+
+        // Newer format: entries point at a pending id plus an optional subPath.
+        let by_id = entry.get("id").and_then(Value::as_str).map(str::to_string);
+        let mut path = match &by_id {
+            Some(id) => match self.pending.get(id) {
+                Some(path) => path.clone(),
+                None => return,
+            },
+            None => take_array(entry, "path"),
+        };
+        path.extend(take_array(entry, "subPath"));
+
+        self.extend_errors(take_array(entry, "errors"));
+        if let Some(Value::Object(extensions)) = entry.remove("extensions") {
+            merge_object(&mut self.result, "extensions", extensions);
+        }
+
+        let Some(root) = self.result.get_mut("data") else {
+            return;
+        };
+
+        if let Some(data) = entry.remove("data") {
+            if let Some(target) = value_at_path(root, &path) {
+                deep_merge(target, data);
+            }
+        } else if let Some(Value::Array(items)) = entry.remove("items") {
+            // `@stream` items are appended to the list. With id-based entries
+            // the path is the list itself, otherwise it ends with the index
+            // of the first item.
+            if by_id.is_none() {
+                path.pop();
+            }
+            if let Some(Value::Array(list)) = value_at_path(root, &path) {
+                list.extend(items);
+            }
+        }
+    }
+
+    fn extend_errors(&mut self, errors: Vec<Value>) {
+        if errors.is_empty() {
+            return;
+        }
+        let target = self
+            .result
+            .entry("errors")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(existing) = target {
+            existing.extend(errors);
+        }
+    }
+
+    /// The merged execution result.
+    pub fn into_result(self) -> Value {
+        Value::Object(self.result)
+    }
+}
+
+fn take_array(object: &mut Map<String, Value>, key: &str) -> Vec<Value> {
+    match object.remove(key) {
+        Some(Value::Array(values)) => values,
+        _ => Vec::new(),
+    }
+}
+
+fn merge_object(target: &mut Map<String, Value>, key: &str, source: Map<String, Value>) {
+    let entry = target
+        .entry(key)
+        .or_insert_with(|| Value::Object(Map::new()));
+    deep_merge(entry, Value::Object(source));
+}
+
+fn value_at_path<'a>(root: &'a mut Value, path: &[Value]) -> Option<&'a mut Value> {
+    path.iter()
+        .try_fold(root, |current, segment| match segment {
+            Value::String(key) => current.get_mut(key.as_str()),
+            Value::Number(index) => current.get_mut(index.as_u64()? as usize),
+            _ => None,
+        })
+}
+
+fn deep_merge(target: &mut Value, source: Value) {
+    match (target, source) {
+        (Value::Object(target), Value::Object(source)) => {
+            for (key, value) in source {
+                match target.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, source) => *target = source,
+    }
+}
+
+/// Returns the boundary of a `multipart/mixed` response.
+fn multipart_boundary(resp: &Response) -> Option<String> {
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)?
+        .to_str()
+        .ok()?;
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/mixed")
+    {
+        return None;
+    }
+    let boundary = params
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .unwrap_or_else(|| "-".to_string());
+    Some(boundary)
+}
+
+/// Sends a GraphQL request that may be answered incrementally. Each part
+/// of a `multipart/mixed` response is reported through `on_event` as it
+/// arrives, followed by a terminal `Complete` or `Error` event, and the
+/// returned body is the merged result. Other responses are returned as is.
+pub async fn send_incremental<F>(
+    request: HttpRequest,
+    mut on_event: F,
+) -> Result<HttpResponse, HttpError>
+where
+    F: FnMut(SubscriptionEvent),
+{
+    let start_time = Instant::now();
+    let resp = send_http(ClientKind::Standard, &request).await?;
+    let head = HttpResponse::head(&resp);
+
+    let Some(boundary) = multipart_boundary(&resp) else {
+        let body = resp
+            .text()
+            .await
+            .map_err(|e| HttpError::new(format!("Failed to read response body: {}", e)))?;
+        return Ok(HttpResponse {
+            body,
+            duration_ms: start_time.elapsed().as_millis(),
+            ..head
+        });
+    };
+
+    let result = read_parts(resp, &boundary, &mut on_event).await;
+    match &result {
+        Ok(_) => on_event(SubscriptionEvent::Complete),
+        Err(e) => on_event(SubscriptionEvent::Error {
+            message: e.message.clone(),
+        }),
+    }
+
+    Ok(HttpResponse {
+        body: result?.to_string(),
+        duration_ms: start_time.elapsed().as_millis(),
+        ..head
+    })
+}
+
+async fn read_parts<F>(resp: Response, boundary: &str, on_event: &mut F) -> Result<Value, HttpError>
+where
+    F: FnMut(SubscriptionEvent),
+{
+    let mut parser = MultipartParser::new(boundary);
+    let mut merger = IncrementalMerger::default();
+    let mut body = resp.bytes_stream();
+
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk
+            .map_err(|e| HttpError::new(format!("Failed to read multipart response: {}", e)))?;
+
+        for part in parser.feed(&chunk) {
+            let text = String::from_utf8_lossy(&part);
+            if text.trim().is_empty() {
+                continue;
+            }
+            let payload: Value = serde_json::from_str(&text)
+                .map_err(|e| HttpError::new(format!("Invalid multipart response part: {}", e)))?;
+            // Some servers send `{}` parts as heartbeats.
+            if payload.as_object().is_some_and(Map::is_empty) {
+                continue;
+            }
+
+            let has_next = payload.get("hasNext").and_then(Value::as_bool);
+            on_event(SubscriptionEvent::Next {
+                payload: text.into_owned(),
+            });
+            merger.apply(payload);
+            if has_next == Some(false) {
+                return Ok(merger.into_result());
+            }
+        }
+
+        if parser.is_finished() {
+            break;
+        }
+    }
+
+    Ok(merger.into_result())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const BODY: &str = "preamble\r\n\
+        --graphql\r\n\
+        Content-Type: application/json; charset=utf-8\r\n\
+        X-Trace: {span}\r\n\
+        \r\n\
+        {\"data\":{\"me\":{\"id\":1}},\"hasNext\":true}\r\n\
+        --graphql\r\n\
+        \r\n\
+        {\"hasNext\":true}\r\n\
+        --graphql\r\n\
+        {\"incremental\":[],\r\n\"hasNext\":false}\r\n\
+        --graphql--\r\n\
+        epilogue";
+
+    fn parts(parser: &mut MultipartParser, chunks: &[&[u8]]) -> Vec<String> {
+        chunks
+            .iter()
+            .flat_map(|chunk| parser.feed(chunk))
+            .map(|part| String::from_utf8(part).unwrap())
+            .collect()
+    }
+
+    fn expected_parts() -> Vec<String> {
+        vec![
+            r#"{"data":{"me":{"id":1}},"hasNext":true}"#.to_string(),
+            r#"{"hasNext":true}"#.to_string(),
+            "{\"incremental\":[],\r\n\"hasNext\":false}".to_string(),
+        ]
+    }
+
+    #[test]
+    fn parses_parts_with_and_without_headers() {
+        let mut parser = MultipartParser::new("graphql");
+        assert_eq!(parts(&mut parser, &[BODY.as_bytes()]), expected_parts());
+        assert!(parser.is_finished());
+        assert!(parser.feed(b"--graphql\r\n\r\n{}\r\n").is_empty());
+    }
+
+    #[test]
+    fn parses_delimiters_split_across_chunks() {
+        let body = BODY.as_bytes();
+        for split in 1..body.len() {
+            let mut parser = MultipartParser::new("graphql");
+            let (first, second) = body.split_at(split);
+            assert_eq!(
+                parts(&mut parser, &[first, second]),
+                expected_parts(),
+                "split at {}",
+                split
+            );
+            assert!(parser.is_finished());
+        }
+
+        let mut parser = MultipartParser::new("graphql");
+        let bytes: Vec<&[u8]> = body.chunks(1).collect();
+        assert_eq!(parts(&mut parser, &bytes), expected_parts());
+    }
+
+    #[test]
+    fn accepts_bare_line_feeds() {
+        let body = BODY.replace("\r\n", "\n");
+        let mut parser = MultipartParser::new("graphql");
+        let mut expected = expected_parts();
+        expected[2] = expected[2].replace("\r\n", "\n");
+        assert_eq!(parts(&mut parser, &[body.as_bytes()]), expected);
+        assert!(parser.is_finished());
+    }
+
+    #[test]
+    fn keeps_part_bodies_that_look_like_headers() {
+        assert_eq!(part_body(b"\r\n{\"a\":\"b: c\"}"), b"{\"a\":\"b: c\"}");
+        assert_eq!(
+            part_body(b"Content-Type: application/json\r\n  ; charset=utf-8\r\n\r\n{}"),
+            b"{}"
+        );
+        assert_eq!(part_body(b"{\"a\":1}\n\n{}"), b"{\"a\":1}\n\n{}");
+    }
+
+    fn merge(payloads: Vec<Value>) -> Value {
+        let mut merger = IncrementalMerger::default();
+        for payload in payloads {
+            merger.apply(payload);
+        }
+        merger.into_result()
+    }
+
+    #[test]
+    fn merges_defer_spec_20220824_payloads() {
+        let result = merge(vec![
+            json!({ "data": { "me": { "id": 1 }, "list": [1] }, "hasNext": true }),
+            json!({
+                "incremental": [
+                    { "path": ["me"], "data": { "name": "Ada" } },
+                    { "path": ["list", 1], "items": [2, 3] },
+                ],
+                "hasNext": true,
+            }),
+            json!({
+                "incremental": [{ "path": ["me"], "data": { "age": 36 }, "errors": [{ "message": "late" }] }],
+                "hasNext": false,
+            }),
+        ]);
+        assert_eq!(
+            result,
+            json!({
+                "data": { "me": { "id": 1, "name": "Ada", "age": 36 }, "list": [1, 2, 3] },
+                "errors": [{ "message": "late" }],
+            })
+        );
+    }
+
+    #[test]
+    fn merges_early_format_payloads() {
+        let result = merge(vec![
+            json!({ "data": { "me": { "id": 1 }, "list": [] }, "hasNext": true }),
+            json!({ "path": ["me"], "data": { "name": "Ada" }, "hasNext": true }),
+            json!({ "path": ["list", 0], "items": ["a"], "hasNext": false }),
+        ]);
+        assert_eq!(
+            result,
+            json!({ "data": { "me": { "id": 1, "name": "Ada" }, "list": ["a"] } })
+        );
+    }
+
+    #[test]
+    fn merges_pending_id_payloads() {
+        let result = merge(vec![
+            json!({
+                "data": { "me": { "id": 1, "profile": {}, "friends": [] } },
+                "pending": [
+                    { "id": "0", "path": ["me"] },
+                    { "id": "1", "path": ["me", "friends"] },
+                ],
+                "hasNext": true,
+            }),
+            json!({
+                "incremental": [
+                    { "id": "0", "subPath": ["profile"], "data": { "bio": "hi" } },
+                    { "id": "1", "items": ["Bob"] },
+                ],
+                "hasNext": true,
+            }),
+            json!({
+                "incremental": [{ "id": "1", "items": ["Eve"] }],
+                "completed": [{ "id": "0" }, { "id": "1", "errors": [{ "message": "cut" }] }],
+                "hasNext": false,
+            }),
+            // Entries of completed operations are ignored.
+            json!({ "incremental": [{ "id": "1", "items": ["Zed"] }], "hasNext": false }),
+        ]);
+        assert_eq!(
+            result,
+            json!({
+                "data": {
+                    "me": { "id": 1, "profile": { "bio": "hi" }, "friends": ["Bob", "Eve"] }
+                },
+                "errors": [{ "message": "cut" }],
+            })
+        );
+    }
+}
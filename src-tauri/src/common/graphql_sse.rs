@@ -0,0 +1,369 @@
+use futures_util::StreamExt;
+use log::{debug, warn};
+use reqwest::{Response, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::ops::ControlFlow;
+use std::time::Instant;
+use tokio::sync::oneshot;
+use typeshare::typeshare;
+
+use crate::common::endpoint_request::insert_header;
+use crate::common::graphql_subscription::SubscriptionEvent;
+use crate::common::http_client::ClientKind;
+use crate::common::proxy_http::{send_http, HttpError, HttpRequest, HttpResponse};
+use crate::common::request_body::HttpBody;
+use crate::common::sse::{SseEvent, SseParser};
+
+/// Header carrying the stream reservation token in single connection mode
+const EVENT_STREAM_TOKEN_HEADER: &str = "X-GraphQL-Event-Stream-Token";
+
+/// Transport mode of the GraphQL over Server-Sent Events protocol (graphql-sse)
+#[typeshare]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum GraphQLStreamMode {
+    /// Every operation opens its own event stream
+    DistinctConnections,
+    /// Operations are multiplexed over one reserved event stream
+    SingleConnection,
+}
+
+enum StreamEnd {
+    /// The server signalled completion
+    Completed,
+    /// The server closed the connection
+    Closed,
+    /// The user stopped the stream
+    Stopped,
+}
+
+/// Executes a GraphQL request whose results are delivered as an event
+/// stream. `request` must be the POST request of the operation. Each result
+/// is reported through `on_event` as it arrives, followed by a terminal
+/// `Complete` or `Error` event. The returned response holds the status and
+/// headers of the event stream and a JSON array of all results as body.
+pub async fn stream_graphql_sse<F>(
+    mode: GraphQLStreamMode,
+    request: HttpRequest,
+    stop: oneshot::Receiver<()>,
+    mut on_event: F,
+) -> Result<HttpResponse, HttpError>
+where
+    F: FnMut(SubscriptionEvent),
+{
+    let result = match mode {
+        GraphQLStreamMode::DistinctConnections => {
+            stream_distinct(request, stop, &mut on_event).await
+        }
+        GraphQLStreamMode::SingleConnection => stream_single(request, stop, &mut on_event).await,
+    };
+
+    match &result {
+        Ok(_) => on_event(SubscriptionEvent::Complete),
+        Err(e) => on_event(SubscriptionEvent::Error {
+            message: e.message.clone(),
+        }),
+    }
+    result
+}
+
+async fn stream_distinct<F>(
+    mut request: HttpRequest,
+    mut stop: oneshot::Receiver<()>,
+    on_event: &mut F,
+) -> Result<HttpResponse, HttpError>
+where
+    F: FnMut(SubscriptionEvent),
+{
+    let start_time = Instant::now();
+    insert_header(
+        request.headers.get_or_insert_with(Default::default),
+        "Accept".to_string(),
+        "text/event-stream".to_string(),
+    );
+
+    let resp = send_http(ClientKind::Streaming, &request).await?;
+    let head = HttpResponse::head(&resp);
+
+    // Servers answer errors (and non-streaming operations) with a regular
+    // response, which is passed through unchanged.
+    if !is_event_stream(&resp) {
+        let body = resp
+            .text()
+            .await
+            .map_err(|e| HttpError::new(format!("Failed to read response body: {}", e)))?;
+        return Ok(HttpResponse {
+            body,
+            duration_ms: start_time.elapsed().as_millis(),
+            ..head
+        });
+    }
+
+    let mut payloads = Vec::new();
+    read_event_stream(resp, &mut stop, |event| {
+        match event.event.as_str() {
+            "next" | "message" if !event.data.is_empty() => {
+                on_event(SubscriptionEvent::Next {
+                    payload: event.data.clone(),
+                });
+                payloads.push(event.data);
+            }
+            "complete" => return Ok(ControlFlow::Break(())),
+            other => debug!("Ignoring '{}' event", other),
+        }
+        Ok(ControlFlow::Continue(()))
+    })
+    .await?;
+
+    Ok(HttpResponse {
+        body: format!("[{}]", payloads.join(",")),
+        duration_ms: start_time.elapsed().as_millis(),
+        ..head
+    })
+}
+
+async fn stream_single<F>(
+    request: HttpRequest,
+    mut stop: oneshot::Receiver<()>,
+    on_event: &mut F,
+) -> Result<HttpResponse, HttpError>
+where
+    F: FnMut(SubscriptionEvent),
+{
+    let start_time = Instant::now();
+    let base_headers = request.headers.clone().unwrap_or_default();
+
+    // 1. Reserve an event stream.
+    let reservation = send_http(
+        ClientKind::Standard,
+        &HttpRequest {
+            method: "PUT".to_string(),
+            url: request.url.clone(),
+            headers: Some(base_headers.clone()),
+            body: None,
+            options: request.options.clone(),
+            endpoint_id: None,
+            signing: request.signing.clone(),
+            cookie_jar: request.cookie_jar.clone(),
+        },
+    )
+    .await?;
+    let token = read_success_body(reservation, "reserve event stream").await?;
+    let token = token.trim().to_string();
+
+    let mut token_headers = base_headers;
+    insert_header(
+        &mut token_headers,
+        EVENT_STREAM_TOKEN_HEADER.to_string(),
+        token,
+    );
+
+    // 2. Open the reserved stream.
+    let mut stream_headers = token_headers.clone();
+    insert_header(
+        &mut stream_headers,
+        "Accept".to_string(),
+        "text/event-stream".to_string(),
+    );
+    stream_headers.retain(|header| !header.name.eq_ignore_ascii_case("content-type"));
+    let resp = send_http(
+        ClientKind::Streaming,
+        &HttpRequest {
+            method: "GET".to_string(),
+            url: request.url.clone(),
+            headers: Some(stream_headers),
+            body: None,
+            options: request.options.clone(),
+            endpoint_id: None,
+            signing: request.signing.clone(),
+            cookie_jar: request.cookie_jar.clone(),
+        },
+    )
+    .await?;
+    if !resp.status().is_success() || !is_event_stream(&resp) {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(HttpError::new(format!(
+            "Failed to open event stream: {} - {}",
+            status, body
+        )));
+    }
+    let head = HttpResponse::head(&resp);
+
+    // 3. Execute the operation on the stream.
+    let operation_id = uuid::Uuid::new_v4().to_string();
+    let body = request.body.as_ref().and_then(HttpBody::as_text);
+    let mut body: Value = serde_json::from_str(body.unwrap_or("{}"))
+        .map_err(|e| HttpError::new(format!("Invalid GraphQL request body: {}", e)))?;
+    body["extensions"]["operationId"] = Value::String(operation_id.clone());
+    let execution = send_http(
+        ClientKind::Standard,
+        &HttpRequest {
+            method: "POST".to_string(),
+            url: request.url.clone(),
+            headers: Some(token_headers.clone()),
+            body: Some(HttpBody::Json(body.to_string())),
+            options: request.options.clone(),
+            endpoint_id: None,
+            signing: request.signing.clone(),
+            cookie_jar: request.cookie_jar.clone(),
+        },
+    )
+    .await?;
+    read_success_body(execution, "execute operation").await?;
+
+    // 4. Until the operation ends, dropping this guard (on errors or when
+    // the request is cancelled) tells the server to stop it.
+    let mut url = Url::parse(&request.url)
+        .map_err(|e| HttpError::new(format!("Invalid endpoint URL: {}", e)))?;
+    url.query_pairs_mut()
+        .append_pair("operationId", &operation_id);
+    let mut operation = RunningOperation {
+        operation_id: operation_id.clone(),
+        stop_request: Some(HttpRequest {
+            method: "DELETE".to_string(),
+            url: url.into(),
+            headers: Some(token_headers),
+            body: None,
+            options: request.options.clone(),
+            endpoint_id: None,
+            signing: request.signing.clone(),
+            cookie_jar: request.cookie_jar.clone(),
+        }),
+    };
+
+    // 5. Forward the results addressed to this operation.
+    let mut payloads = Vec::new();
+    let end = read_event_stream(resp, &mut stop, |event| {
+        let message: Value = match event.event.as_str() {
+            "next" | "complete" => serde_json::from_str(&event.data)
+                .map_err(|e| HttpError::new(format!("Invalid {} event: {}", event.event, e)))?,
+            other => {
+                debug!("Ignoring '{}' event", other);
+                return Ok(ControlFlow::Continue(()));
+            }
+        };
+        if message.get("id").and_then(Value::as_str) != Some(operation_id.as_str()) {
+            return Ok(ControlFlow::Continue(()));
+        }
+        if event.event == "complete" {
+            return Ok(ControlFlow::Break(()));
+        }
+        let payload = message
+            .get("payload")
+            .cloned()
+            .unwrap_or(Value::Null)
+            .to_string();
+        on_event(SubscriptionEvent::Next {
+            payload: payload.clone(),
+        });
+        payloads.push(payload);
+        Ok(ControlFlow::Continue(()))
+    })
+    .await?;
+
+    // 6. Tell the server to stop an operation the user cancelled.
+    match end {
+        StreamEnd::Stopped => operation.stop().await,
+        StreamEnd::Completed | StreamEnd::Closed => operation.finished(),
+    }
+
+    Ok(HttpResponse {
+        body: format!("[{}]", payloads.join(",")),
+        duration_ms: start_time.elapsed().as_millis(),
+        ..head
+    })
+}
+
+/// Operation executing on a single connection event stream
+struct RunningOperation {
+    operation_id: String,
+    /// DELETE request stopping the operation, `None` once it ended
+    stop_request: Option<HttpRequest>,
+}
+
+impl RunningOperation {
+    fn finished(&mut self) {
+        self.stop_request = None;
+    }
+
+    async fn stop(&mut self) {
+        if let Some(request) = self.stop_request.take() {
+            send_stop(&self.operation_id, &request).await;
+        }
+    }
+}
+
+impl Drop for RunningOperation {
+    fn drop(&mut self) {
+        let Some(request) = self.stop_request.take() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!("Cannot stop operation {}: no runtime", self.operation_id);
+            return;
+        };
+        let operation_id = std::mem::take(&mut self.operation_id);
+        runtime.spawn(async move { send_stop(&operation_id, &request).await });
+    }
+}
+
+async fn send_stop(operation_id: &str, request: &HttpRequest) {
+    if let Err(e) = send_http(ClientKind::Standard, request).await {
+        warn!("Failed to stop operation {}: {}", operation_id, e.message);
+    }
+}
+
+fn is_event_stream(resp: &Response) -> bool {
+    resp.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim_start().starts_with("text/event-stream"))
+}
+
+async fn read_success_body(resp: Response, action: &str) -> Result<String, HttpError> {
+    let status = resp.status();
+    let body = resp
+        .text()
+        .await
+        .map_err(|e| HttpError::new(format!("Failed to read response body: {}", e)))?;
+    if !status.is_success() {
+        return Err(HttpError::new(format!(
+            "Failed to {}: {} - {}",
+            action, status, body
+        )));
+    }
+    Ok(body)
+}
+
+/// Parses an event stream body, passing each event to `handle` until it
+/// breaks, the server closes the stream or `stop` fires.
+async fn read_event_stream<H>(
+    resp: Response,
+    stop: &mut oneshot::Receiver<()>,
+    mut handle: H,
+) -> Result<StreamEnd, HttpError>
+where
+    H: FnMut(SseEvent) -> Result<ControlFlow<()>, HttpError>,
+{
+    let mut parser = SseParser::default();
+    let mut body = resp.bytes_stream();
+
+    loop {
+        tokio::select! {
+            _ = &mut *stop => return Ok(StreamEnd::Stopped),
+            chunk = body.next() => {
+                let chunk = match chunk {
+                    Some(chunk) => chunk.map_err(|e| HttpError::new(format!("Failed to read event stream: {}", e)))?,
+                    None => return Ok(StreamEnd::Closed),
+                };
+                for event in parser.feed(&chunk) {
+                    if handle(event)?.is_break() {
+                        return Ok(StreamEnd::Completed);
+                    }
+                }
+            }
+        }
+    }
+}
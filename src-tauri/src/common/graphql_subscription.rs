@@ -0,0 +1,555 @@
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, warn};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use strum_macros::{Display, EnumString};
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, http::HeaderValue, Message};
+use typeshare::typeshare;
+
+use crate::common::proxy_http::HttpHeader;
+
+/// How long to wait for the server to acknowledge `connection_init`
+const CONNECTION_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// WebSocket subprotocol spoken with the subscription server
+#[typeshare]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Display, EnumString)]
+#[serde(rename_all = "kebab-case")]
+pub enum SubscriptionProtocol {
+    /// `graphql-transport-ws`, the protocol of the `graphql-ws` library
+    #[strum(serialize = "graphql-transport-ws")]
+    GraphqlTransportWs,
+    /// `graphql-ws`, the legacy `subscriptions-transport-ws` protocol
+    #[strum(serialize = "graphql-ws")]
+    GraphqlWs,
+}
+
+/// Event streamed to the frontend for a running subscription
+#[typeshare]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "content", rename_all = "camelCase")]
+pub enum SubscriptionEvent {
+    /// The server acknowledged the connection using the given protocol
+    Connected { protocol: SubscriptionProtocol },
+    /// A JSON string execution result (`next` or `data` message)
+    Next { payload: String },
+    /// The subscription failed; no further events follow
+    Error { message: String },
+    /// The subscription ended, either by the server or by the user
+    Complete,
+}
+
+/// Everything needed to open a subscription, independent of Tauri so the
+/// transport can be driven against any WebSocket server.
+#[derive(Debug, Clone)]
+pub struct SubscriptionRequest {
+    /// `ws://` or `wss://` URL of the subscription server
+    pub url: String,
+    /// Subprotocol to request; both are offered when `None`
+    pub protocol: Option<SubscriptionProtocol>,
+    /// Headers sent with the WebSocket handshake
+    pub headers: Vec<HttpHeader>,
+    /// Payload of the `connection_init` message
+    pub connection_params: Option<Value>,
+    pub query: String,
+    pub variables: Option<Value>,
+    pub operation_name: Option<String>,
+}
+
+/// Running subscriptions, keyed by subscription id. Stored in Tauri state.
+#[derive(Default)]
+pub struct SubscriptionRegistry {
+    subscriptions: Mutex<HashMap<String, oneshot::Sender<()>>>,
+}
+
+impl SubscriptionRegistry {
+    pub fn register(&self, id: String) -> oneshot::Receiver<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.subscriptions
+            .lock()
+            .expect("subscription registry poisoned")
+            .insert(id, sender);
+        receiver
+    }
+
+    /// Asks a subscription to stop. Returns `false` if it is not running.
+    pub fn stop(&self, id: &str) -> bool {
+        self.remove(id)
+            .map(|sender| sender.send(()).is_ok())
+            .unwrap_or(false)
+    }
+
+    pub fn remove(&self, id: &str) -> Option<oneshot::Sender<()>> {
+        self.subscriptions
+            .lock()
+            .expect("subscription registry poisoned")
+            .remove(id)
+    }
+
+    /// Like `register`, but the subscription is removed again when the
+    /// returned guard is dropped, even if the future running it is aborted.
+    pub fn register_scoped(&self, id: String) -> (Registration<'_>, oneshot::Receiver<()>) {
+        let stop = self.register(id.clone());
+        (Registration { registry: self, id }, stop)
+    }
+}
+
+/// Entry of a `SubscriptionRegistry` that is removed on drop
+pub struct Registration<'a> {
+    registry: &'a SubscriptionRegistry,
+    id: String,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.registry.remove(&self.id);
+    }
+}
+
+/// Derives the WebSocket URL of an HTTP endpoint by swapping the scheme.
+pub fn derive_ws_url(endpoint: &str) -> Result<String, String> {
+    let mut url = Url::parse(endpoint).map_err(|e| format!("Invalid endpoint URL: {}", e))?;
+    let scheme = match url.scheme() {
+        "http" | "ws" => "ws",
+        "https" | "wss" => "wss",
+        other => return Err(format!("Unsupported URL scheme: {}", other)),
+    };
+    url.set_scheme(scheme)
+        .map_err(|_| format!("Cannot use scheme {} for {}", scheme, endpoint))?;
+    Ok(url.into())
+}
+
+/// Runs a subscription until the server completes it, an error occurs or
+/// `stop` fires. Every message is reported through `on_event`, and a
+/// terminal `Error` or `Complete` event is always emitted last.
+pub async fn run_subscription<F>(
+    request: SubscriptionRequest,
+    stop: oneshot::Receiver<()>,
+    mut on_event: F,
+) where
+    F: FnMut(SubscriptionEvent),
+{
+    match subscribe(request, stop, &mut on_event).await {
+        Ok(()) => on_event(SubscriptionEvent::Complete),
+        Err(message) => on_event(SubscriptionEvent::Error { message }),
+    }
+}
+
+async fn subscribe<F>(
+    request: SubscriptionRequest,
+    mut stop: oneshot::Receiver<()>,
+    on_event: &mut F,
+) -> Result<(), String>
+where
+    F: FnMut(SubscriptionEvent),
+{
+    let mut ws_request = request
+        .url
+        .as_str()
+        .into_client_request()
+        .map_err(|e| format!("Invalid subscription URL: {}", e))?;
+
+    let offered = match request.protocol {
+        Some(protocol) => protocol.to_string(),
+        None => format!(
+            "{}, {}",
+            SubscriptionProtocol::GraphqlTransportWs,
+            SubscriptionProtocol::GraphqlWs
+        ),
+    };
+    let ws_headers = ws_request.headers_mut();
+    for header in &request.headers {
+        let name = header
+            .name
+            .parse::<tokio_tungstenite::tungstenite::http::HeaderName>()
+            .map_err(|e| format!("Invalid header name {}: {}", header.name, e))?;
+        let value = HeaderValue::from_str(&header.value)
+            .map_err(|e| format!("Invalid header value for {}: {}", header.name, e))?;
+        ws_headers.append(name, value);
+    }
+    ws_headers.insert(
+        "Sec-WebSocket-Protocol",
+        HeaderValue::from_str(&offered).map_err(|e| e.to_string())?,
+    );
+
+    let (mut socket, response) = tokio_tungstenite::connect_async(ws_request)
+        .await
+        .map_err(|e| format!("WebSocket connection failed: {}", e))?;
+
+    // Servers that ignore the header usually speak the legacy protocol.
+    let protocol = response
+        .headers()
+        .get("Sec-WebSocket-Protocol")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<SubscriptionProtocol>().ok())
+        .or(request.protocol)
+        .unwrap_or(SubscriptionProtocol::GraphqlWs);
+    debug!(
+        "Subscription connected to {} using {}",
+        request.url, protocol
+    );
+
+    let init = match &request.connection_params {
+        Some(params) => json!({ "type": "connection_init", "payload": params }),
+        None => json!({ "type": "connection_init" }),
+    };
+    socket
+        .send(Message::text(init.to_string()))
+        .await
+        .map_err(|e| format!("Failed to send connection_init: {}", e))?;
+
+    // Wait for connection_ack, answering pings and skipping keep-alives.
+    tokio::time::timeout(CONNECTION_ACK_TIMEOUT, async {
+        while let Some(message) = socket.next().await {
+            let message = message.map_err(|e| format!("WebSocket error: {}", e))?;
+            let Some(frame) = parse_frame(&message)? else {
+                if let Message::Close(close) = message {
+                    return Err(format!(
+                        "Connection closed before acknowledgement: {:?}",
+                        close
+                    ));
+                }
+                continue;
+            };
+            match frame.kind.as_str() {
+                "connection_ack" => return Ok(()),
+                "ping" => {
+                    socket
+                        .send(Message::text(json!({ "type": "pong" }).to_string()))
+                        .await
+                        .map_err(|e| e.to_string())?;
+                }
+                "connection_error" => {
+                    return Err(format!(
+                        "Connection rejected: {}",
+                        frame.payload.unwrap_or(Value::Null)
+                    ))
+                }
+                "ka" => {}
+                other => warn!("Ignoring '{}' message before connection_ack", other),
+            }
+        }
+        Err("Connection closed before acknowledgement".to_string())
+    })
+    .await
+    .map_err(|_| "Timed out waiting for connection_ack".to_string())??;
+
+    on_event(SubscriptionEvent::Connected { protocol });
+
+    let operation_id = uuid::Uuid::new_v4().to_string();
+    let start_type = match protocol {
+        SubscriptionProtocol::GraphqlTransportWs => "subscribe",
+        SubscriptionProtocol::GraphqlWs => "start",
+    };
+    let start = json!({
+        "id": operation_id,
+        "type": start_type,
+        "payload": {
+            "query": request.query,
+            "variables": request.variables,
+            "operationName": request.operation_name,
+        },
+    });
+    socket
+        .send(Message::text(start.to_string()))
+        .await
+        .map_err(|e| format!("Failed to start subscription: {}", e))?;
+
+    loop {
+        tokio::select! {
+            _ = &mut stop => {
+                let stop_type = match protocol {
+                    SubscriptionProtocol::GraphqlTransportWs => "complete",
+                    SubscriptionProtocol::GraphqlWs => "stop",
+                };
+                let _ = socket
+                    .send(Message::text(json!({ "id": operation_id, "type": stop_type }).to_string()))
+                    .await;
+                if protocol == SubscriptionProtocol::GraphqlWs {
+                    let _ = socket
+                        .send(Message::text(json!({ "type": "connection_terminate" }).to_string()))
+                        .await;
+                }
+                let _ = socket.close(None).await;
+                return Ok(());
+            }
+            message = socket.next() => {
+                let Some(message) = message else {
+                    return Err("Connection closed by server".to_string());
+                };
+                let message = message.map_err(|e| format!("WebSocket error: {}", e))?;
+                if let Message::Close(close) = &message {
+                    return match close {
+                        Some(frame) if u16::from(frame.code) != 1000 => Err(format!(
+                            "Connection closed by server: {} {}",
+                            u16::from(frame.code),
+                            frame.reason
+                        )),
+                        _ => Ok(()),
+                    };
+                }
+                let Some(frame) = parse_frame(&message)? else {
+                    continue;
+                };
+                if frame.id.as_deref().is_some_and(|id| id != operation_id) {
+                    continue;
+                }
+                match frame.kind.as_str() {
+                    "next" | "data" => on_event(SubscriptionEvent::Next {
+                        payload: frame.payload.unwrap_or(Value::Null).to_string(),
+                    }),
+                    "error" => {
+                        return Err(format!(
+                            "Subscription error: {}",
+                            frame.payload.unwrap_or(Value::Null)
+                        ))
+                    }
+                    "complete" => {
+                        let _ = socket.close(None).await;
+                        return Ok(());
+                    }
+                    "ping" => {
+                        socket
+                            .send(Message::text(json!({ "type": "pong" }).to_string()))
+                            .await
+                            .map_err(|e| e.to_string())?;
+                    }
+                    "pong" | "ka" => {}
+                    other => debug!("Ignoring subscription message '{}'", other),
+                }
+            }
+        }
+    }
+}
+
+struct Frame {
+    kind: String,
+    id: Option<String>,
+    payload: Option<Value>,
+}
+
+/// Decodes a protocol message. Non-text frames yield `None`.
+fn parse_frame(message: &Message) -> Result<Option<Frame>, String> {
+    let text = match message {
+        Message::Text(text) => text.as_str(),
+        Message::Binary(bytes) => std::str::from_utf8(bytes).map_err(|e| e.to_string())?,
+        _ => return Ok(None),
+    };
+    let mut value: Value =
+        serde_json::from_str(text).map_err(|e| format!("Invalid subscription message: {}", e))?;
+    let kind = value
+        .get("type")
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("Subscription message without type: {}", text))?
+        .to_string();
+    let id = value.get("id").and_then(Value::as_str).map(str::to_string);
+    let payload = value.get_mut("payload").map(Value::take);
+    Ok(Some(Frame { kind, id, payload }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+    use tokio_tungstenite::WebSocketStream;
+
+    type ServerSocket = WebSocketStream<TcpStream>;
+
+    async fn listen() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/graphql", listener.local_addr().unwrap());
+        (listener, url)
+    }
+
+    /// Accepts one connection, answering the handshake with `protocol`, and
+    /// returns the subprotocols offered by the client.
+    #[allow(clippy::result_large_err)]
+    async fn accept(listener: &TcpListener, protocol: &str) -> (ServerSocket, String) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut offered = String::new();
+        let socket = tokio_tungstenite::accept_hdr_async(
+            stream,
+            |request: &Request, mut response: Response| {
+                offered = request.headers()["Sec-WebSocket-Protocol"]
+                    .to_str()
+                    .unwrap()
+                    .to_string();
+                response.headers_mut().insert(
+                    "Sec-WebSocket-Protocol",
+                    HeaderValue::from_str(protocol).unwrap(),
+                );
+                Ok(response)
+            },
+        )
+        .await
+        .unwrap();
+        (socket, offered)
+    }
+
+    async fn receive(socket: &mut ServerSocket) -> Value {
+        loop {
+            if let Message::Text(text) = socket.next().await.unwrap().unwrap() {
+                return serde_json::from_str(text.as_str()).unwrap();
+            }
+        }
+    }
+
+    async fn send(socket: &mut ServerSocket, message: Value) {
+        socket
+            .send(Message::text(message.to_string()))
+            .await
+            .unwrap();
+    }
+
+    fn request(url: String, protocol: Option<SubscriptionProtocol>) -> SubscriptionRequest {
+        SubscriptionRequest {
+            url,
+            protocol,
+            headers: Vec::new(),
+            connection_params: None,
+            query: "subscription { ticks }".to_string(),
+            variables: None,
+            operation_name: None,
+        }
+    }
+
+    /// Runs a subscription, stopping it after `stop_after` results when set.
+    async fn collect(request: SubscriptionRequest, stop_after: Option<usize>) -> Value {
+        let (stop_sender, stop) = oneshot::channel();
+        let mut stop_sender = Some(stop_sender);
+        let mut events = Vec::new();
+        run_subscription(request, stop, |event| {
+            if matches!(event, SubscriptionEvent::Next { .. }) && stop_after == Some(1) {
+                stop_sender.take().map(|sender| sender.send(()));
+            }
+            events.push(event);
+        })
+        .await;
+        serde_json::to_value(events).unwrap()
+    }
+
+    #[tokio::test]
+    async fn speaks_graphql_transport_ws() {
+        let (listener, url) = listen().await;
+        let server = tokio::spawn(async move {
+            let (mut socket, offered) = accept(&listener, "graphql-transport-ws").await;
+            assert_eq!(offered, "graphql-transport-ws, graphql-ws");
+            assert_eq!(
+                receive(&mut socket).await,
+                json!({ "type": "connection_init", "payload": { "token": "secret" } })
+            );
+            send(&mut socket, json!({ "type": "connection_ack" })).await;
+
+            let subscribe = receive(&mut socket).await;
+            assert_eq!(subscribe["type"], "subscribe");
+            assert_eq!(subscribe["payload"]["query"], "subscription { ticks }");
+            let id = subscribe["id"].clone();
+
+            send(&mut socket, json!({ "type": "ping" })).await;
+            assert_eq!(receive(&mut socket).await, json!({ "type": "pong" }));
+            let result = json!({ "data": { "ticks": 1 } });
+            send(
+                &mut socket,
+                json!({ "id": "other", "type": "next", "payload": result }),
+            )
+            .await;
+            send(
+                &mut socket,
+                json!({ "id": id, "type": "next", "payload": result }),
+            )
+            .await;
+            send(&mut socket, json!({ "id": id, "type": "complete" })).await;
+        });
+
+        let mut request = request(url, None);
+        request.connection_params = Some(json!({ "token": "secret" }));
+        let events = collect(request, None).await;
+        server.await.unwrap();
+
+        assert_eq!(
+            events,
+            json!([
+                { "type": "connected", "content": { "protocol": "graphql-transport-ws" } },
+                { "type": "next", "content": { "payload": "{\"data\":{\"ticks\":1}}" } },
+                { "type": "complete" },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn speaks_graphql_ws_and_stops_on_request() {
+        let (listener, url) = listen().await;
+        let server = tokio::spawn(async move {
+            let (mut socket, offered) = accept(&listener, "graphql-ws").await;
+            assert_eq!(offered, "graphql-ws");
+            assert_eq!(
+                receive(&mut socket).await,
+                json!({ "type": "connection_init" })
+            );
+            send(&mut socket, json!({ "type": "ka" })).await;
+            send(&mut socket, json!({ "type": "connection_ack" })).await;
+
+            let start = receive(&mut socket).await;
+            assert_eq!(start["type"], "start");
+            let id = start["id"].clone();
+            send(
+                &mut socket,
+                json!({ "id": id, "type": "data", "payload": { "data": { "ticks": 1 } } }),
+            )
+            .await;
+
+            assert_eq!(
+                receive(&mut socket).await,
+                json!({ "id": id, "type": "stop" })
+            );
+            assert_eq!(
+                receive(&mut socket).await,
+                json!({ "type": "connection_terminate" })
+            );
+            assert!(matches!(socket.next().await, Some(Ok(Message::Close(_)))));
+        });
+
+        let events = collect(request(url, Some(SubscriptionProtocol::GraphqlWs)), Some(1)).await;
+        server.await.unwrap();
+
+        assert_eq!(
+            events,
+            json!([
+                { "type": "connected", "content": { "protocol": "graphql-ws" } },
+                { "type": "next", "content": { "payload": "{\"data\":{\"ticks\":1}}" } },
+                { "type": "complete" },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_rejected_connections() {
+        let (listener, url) = listen().await;
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = accept(&listener, "graphql-ws").await;
+            receive(&mut socket).await;
+            send(
+                &mut socket,
+                json!({ "type": "connection_error", "payload": { "message": "denied" } }),
+            )
+            .await;
+        });
+
+        let events = collect(request(url, None), None).await;
+        server.await.unwrap();
+
+        assert_eq!(
+            events,
+            json!([{
+                "type": "error",
+                "content": { "message": "Connection rejected: {\"message\":\"denied\"}" },
+            }])
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::common::request_body::MultipartPart;
+
+/// Header that servers with CSRF prevention, such as Apollo Server, require
+/// on multipart requests
+pub const PREFLIGHT_HEADER: &str = "Apollo-Require-Preflight";
+
+/// Builds the fields of a GraphQL multipart request following
+/// https://github.com/jaydenseric/graphql-multipart-request-spec: the
+/// `operations` JSON with `null` in place of every file, the `map` from
+/// file fields to the variables they fill, then one field per file.
+///
+/// `files` maps variable paths such as `variables.file` or
+/// `variables.files.0` to local file paths; the `variables.` prefix may be
+/// left out. A file used for several variables is sent once.
+pub fn upload_parts(
+    mut operations: Value,
+    files: &HashMap<String, String>,
+) -> Result<Vec<MultipartPart>, String> {
+    let mut paths_by_file: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (path, file) in files {
+        let path = path.trim();
+        let path = if path.starts_with("variables.") {
+            path.to_string()
+        } else {
+            format!("variables.{}", path)
+        };
+        set_null(&mut operations, &path)?;
+        paths_by_file.entry(file.as_str()).or_default().push(path);
+    }
+
+    let mut map = Map::new();
+    let mut file_parts = Vec::new();
+    for (index, (file, mut paths)) in paths_by_file.into_iter().enumerate() {
+        paths.sort();
+        map.insert(
+            index.to_string(),
+            Value::Array(paths.into_iter().map(Value::String).collect()),
+        );
+        file_parts.push(MultipartPart::file(index.to_string(), file));
+    }
+
+    let mut parts = vec![
+        MultipartPart::text("operations", operations.to_string()),
+        MultipartPart::text("map", Value::Object(map).to_string()),
+    ];
+    parts.extend(file_parts);
+    Ok(parts)
+}
+
+/// Sets the value at a dot-separated path to `null`, creating the objects
+/// and list entries leading to it.
+fn set_null(operations: &mut Value, path: &str) -> Result<(), String> {
+    let mut target = operations;
+    for segment in path.split('.') {
+        if segment.is_empty() {
+            return Err(format!("Invalid variable path: '{}'", path));
+        }
+        let index = segment.parse::<usize>().ok();
+        if target.is_null() {
+            *target = match index {
+                Some(_) => Value::Array(Vec::new()),
+                None => Value::Object(Map::new()),
+            };
+        }
+        target = match (target, index) {
+            (Value::Object(fields), _) => fields.entry(segment).or_insert(Value::Null),
+            (Value::Array(items), Some(index)) => {
+                if items.len() <= index {
+                    items.resize(index + 1, Value::Null);
+                }
+                &mut items[index]
+            }
+            _ => return Err(format!("Cannot place a file at {}", path)),
+        };
+    }
+    *target = Value::Null;
+    Ok(())
+}
@@ -1,16 +1,306 @@
-use log::{error, info};
+use log::info;
 use once_cell::sync::Lazy;
-use reqwest::Client;
-use std::time::Duration;
+use reqwest::{redirect::Policy, Certificate, Client, NoProxy, Proxy};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use typeshare::typeshare;
 
-pub static HTTP_CLIENT: Lazy<Client> = Lazy::new(|| {
-    info!("Initializing HTTP client with a timeout of 30 seconds");
+use crate::common::client_certificate::ClientCertificate;
+use crate::common::http_timing::{TimedConnectLayer, TimedResolver};
+use crate::common::response_body::ContentCoding;
+use crate::common::retry::RetryPolicy;
 
-    match Client::builder().timeout(Duration::from_secs(30)).build() {
-        Ok(client) => client,
-        Err(e) => {
-            error!("Failed to build HTTP client: {}", e);
-            panic!("Failed to build HTTP client");
+/// Timeout used when a request does not set one
+const DEFAULT_TIMEOUT_MS: u32 = 30_000;
+/// Redirect limit used when following redirects without `max_redirects`
+const DEFAULT_MAX_REDIRECTS: u32 = 10;
+
+/// Network options of a request. Unset fields fall back to the endpoint's
+/// `EndpointConfig.request_options`, then to the built-in defaults.
+#[typeshare]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct RequestOptions {
+    /// Request timeout in milliseconds (connect timeout for streams), 30s by default
+    pub timeout_ms: Option<u32>,
+    /// Whether redirects are followed, true by default
+    pub follow_redirects: Option<bool>,
+    /// Maximum number of redirects to follow, 10 by default
+    pub max_redirects: Option<u32>,
+    /// Skip TLS certificate and hostname verification
+    pub accept_invalid_certs: Option<bool>,
+    /// Path of a PEM file with additional trusted CA certificates
+    pub ca_bundle_path: Option<String>,
+    /// Proxy for all requests, e.g. `http://proxy.local:3128`
+    pub proxy_url: Option<String>,
+    /// Hosts, domains or CIDR ranges that bypass `proxy_url`
+    pub no_proxy: Option<Vec<String>>,
+    /// Identity presented to servers that require mutual TLS
+    pub client_certificate: Option<ClientCertificate>,
+    /// Largest response body kept in memory, in bytes; larger bodies are
+    /// written to a temporary file. 10 MB by default
+    pub max_body_bytes: Option<u32>,
+    /// Sending requests again when they fail, not retried by default
+    pub retry: Option<RetryPolicy>,
+    /// HTTP version to speak, negotiated with the server by default
+    pub http_version: Option<HttpVersionMode>,
+    /// Compressions offered in `Accept-Encoding`, unless the request's
+    /// headers set one; responses using them are decompressed. None are
+    /// offered by default
+    pub accept_encoding: Option<Vec<ContentCoding>>,
+    /// Hosts connected to at a fixed address instead of the one they
+    /// resolve to, like curl's `--resolve`
+    pub resolve: Option<Vec<HostOverride>>,
+    /// Unix domain socket connected to instead of the URL's host, set from
+    /// `unix://` URLs by `use_unix_socket`
+    #[serde(skip)]
+    pub unix_socket: Option<String>,
+}
+
+/// A host resolved to a fixed address rather than looked up
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct HostOverride {
+    /// Host name as it appears in URLs, e.g. `api.example.com`
+    pub host: String,
+    /// IP address, optionally with a port, e.g. `10.0.0.5` or
+    /// `10.0.0.5:8443`. The port replaces the default one of the scheme,
+    /// but not a port given in the URL.
+    pub address: String,
+}
+
+/// Which HTTP version a client speaks
+#[typeshare]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum HttpVersionMode {
+    /// HTTP/2 when the server picks it during the TLS handshake (ALPN),
+    /// HTTP/1.1 otherwise and over plain HTTP
+    Negotiate,
+    /// HTTP/1.1 only
+    Http1,
+    /// HTTP/2 without negotiation ("prior knowledge"), also over plain
+    /// HTTP; fails with servers that do not speak it
+    Http2,
+}
+
+impl RequestOptions {
+    /// Fills the fields that are not set from `defaults`.
+    pub fn or(self, defaults: &RequestOptions) -> RequestOptions {
+        RequestOptions {
+            timeout_ms: self.timeout_ms.or(defaults.timeout_ms),
+            follow_redirects: self.follow_redirects.or(defaults.follow_redirects),
+            max_redirects: self.max_redirects.or(defaults.max_redirects),
+            accept_invalid_certs: self.accept_invalid_certs.or(defaults.accept_invalid_certs),
+            ca_bundle_path: self
+                .ca_bundle_path
+                .or_else(|| defaults.ca_bundle_path.clone()),
+            proxy_url: self.proxy_url.or_else(|| defaults.proxy_url.clone()),
+            no_proxy: self.no_proxy.or_else(|| defaults.no_proxy.clone()),
+            client_certificate: self
+                .client_certificate
+                .or_else(|| defaults.client_certificate.clone()),
+            max_body_bytes: self.max_body_bytes.or(defaults.max_body_bytes),
+            retry: self.retry.or_else(|| defaults.retry.clone()),
+            http_version: self.http_version.or(defaults.http_version),
+            accept_encoding: self
+                .accept_encoding
+                .or_else(|| defaults.accept_encoding.clone()),
+            resolve: self.resolve.or_else(|| defaults.resolve.clone()),
+            unix_socket: self.unix_socket.or_else(|| defaults.unix_socket.clone()),
+        }
+    }
+
+    /// How many redirects are followed, `None` when they are not.
+    pub fn redirect_limit(&self) -> Option<u32> {
+        self.follow_redirects
+            .unwrap_or(true)
+            .then(|| self.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS))
+    }
+}
+
+/// How a client is going to be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClientKind {
+    /// Regular requests; the whole exchange is time-limited
+    Standard,
+    /// Long-lived streaming responses (SSE, multipart). Only connecting is
+    /// time-limited since the body may stay open indefinitely.
+    Streaming,
+}
+
+/// Most clients kept at once; the least recently used one is dropped,
+/// together with its connection pool, when another one is needed
+const MAX_CLIENTS: usize = 16;
+
+/// What a cached client was built from: the options that shape it, and
+/// the modification times of the files it read, so edited certificates
+/// are picked up
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClientKey {
+    kind: ClientKind,
+    options: RequestOptions,
+    file_times: Vec<Option<SystemTime>>,
+}
+
+impl ClientKey {
+    fn new(kind: ClientKind, options: Option<&RequestOptions>) -> Self {
+        let mut options = options.cloned().unwrap_or_default();
+        // Applied per request rather than when building the client.
+        options.max_body_bytes = None;
+        options.retry = None;
+        options.accept_encoding = None;
+
+        let certificate = options.client_certificate.as_ref();
+        let file_times = [
+            options.ca_bundle_path.as_ref(),
+            certificate.and_then(|certificate| certificate.cert_path.as_ref()),
+            certificate.and_then(|certificate| certificate.key_path.as_ref()),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|path| {
+            std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect();
+
+        Self {
+            kind,
+            options,
+            file_times,
+        }
+    }
+}
+
+/// Clients built so far with the time they were last used, so connections
+/// are pooled across requests that share options.
+static CLIENTS: Lazy<Mutex<HashMap<ClientKey, (Client, u64)>>> = Lazy::new(Default::default);
+static CLIENT_USES: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a client configured with `options`, building and caching it on
+/// first use.
+pub fn client_for(kind: ClientKind, options: Option<&RequestOptions>) -> Result<Client, String> {
+    let key = ClientKey::new(kind, options);
+    let used = CLIENT_USES.fetch_add(1, Ordering::Relaxed);
+    let mut clients = CLIENTS.lock().expect("HTTP client cache poisoned");
+    if let Some((client, last_used)) = clients.get_mut(&key) {
+        *last_used = used;
+        return Ok(client.clone());
+    }
+
+    info!("Building {:?} HTTP client for {:?}", kind, key.options);
+    let client = build_client(kind, &key.options)?;
+    if clients.len() >= MAX_CLIENTS {
+        let oldest = clients
+            .iter()
+            .min_by_key(|(_, (_, last_used))| *last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            clients.remove(&oldest);
         }
     }
-});
+    clients.insert(key, (client.clone(), used));
+    Ok(client)
+}
+
+fn build_client(kind: ClientKind, options: &RequestOptions) -> Result<Client, String> {
+    let timeout = Duration::from_millis(options.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS).into());
+    let mut builder = match kind {
+        ClientKind::Standard => Client::builder().timeout(timeout),
+        ClientKind::Streaming => Client::builder().connect_timeout(timeout),
+    };
+    let overrides = host_overrides(options.resolve.as_deref().unwrap_or_default())?;
+    builder = builder
+        .dns_resolver(Arc::new(TimedResolver::new(overrides)))
+        .connector_layer(TimedConnectLayer);
+
+    if let Some(path) = &options.unix_socket {
+        #[cfg(unix)]
+        {
+            builder = builder.unix_socket(path.as_str());
+        }
+        #[cfg(not(unix))]
+        return Err(format!("Unix sockets are not supported here: {}", path));
+    }
+
+    builder = match options.http_version.unwrap_or(HttpVersionMode::Negotiate) {
+        HttpVersionMode::Negotiate => builder,
+        HttpVersionMode::Http1 => builder.http1_only(),
+        HttpVersionMode::Http2 => builder.http2_prior_knowledge(),
+    };
+
+    builder = builder.redirect(match options.redirect_limit() {
+        Some(limit) => Policy::limited(limit.try_into().unwrap_or(usize::MAX)),
+        None => Policy::none(),
+    });
+
+    if options.accept_invalid_certs.unwrap_or(false) {
+        builder = builder
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+    }
+
+    if let Some(path) = &options.ca_bundle_path {
+        let pem =
+            std::fs::read(path).map_err(|e| format!("Failed to read CA bundle {}: {}", path, e))?;
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .map_err(|e| format!("Invalid CA bundle {}: {}", path, e))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    if let Some(certificate) = &options.client_certificate {
+        builder = builder.identity(certificate.identity()?);
+    }
+
+    if let Some(proxy_url) = &options.proxy_url {
+        let no_proxy = options
+            .no_proxy
+            .as_ref()
+            .and_then(|hosts| NoProxy::from_string(&hosts.join(",")));
+        let proxy = Proxy::all(proxy_url)
+            .map_err(|e| format!("Invalid proxy URL {}: {}", proxy_url, e))?
+            .no_proxy(no_proxy);
+        builder = builder.proxy(proxy);
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", error_chain(&e)))
+}
+
+/// Parses `RequestOptions.resolve` into addresses keyed by lowercase host.
+/// Addresses without a port get port 0, which the connector replaces with
+/// the port of the URL.
+fn host_overrides(overrides: &[HostOverride]) -> Result<HashMap<String, SocketAddr>, String> {
+    overrides
+        .iter()
+        .map(|entry| {
+            let address = entry.address.trim();
+            let addr = address
+                .parse::<SocketAddr>()
+                .or_else(|_| address.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 0)))
+                .map_err(|_| format!("Invalid address for {}: {}", entry.host, address))?;
+            Ok((entry.host.trim().to_ascii_lowercase(), addr))
+        })
+        .collect()
+}
+
+/// Formats an error with its sources; reqwest keeps TLS details such as a
+/// mismatched client key or a wrong password in the source.
+pub fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+    message
+}
@@ -0,0 +1,323 @@
+use hyper::body::{Body, Frame, SizeHint};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::{Request, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower_layer::Layer;
+use tower_service::Service;
+use typeshare::typeshare;
+
+use crate::common::proxy_http::response_headers;
+
+/// Where the time of a request went. Phases that did not happen, such as
+/// connecting when a pooled connection was reused, are not set. When
+/// redirects are followed, the phases of all hops add up.
+///
+/// reqwest opens connections in one step, so the TLS handshake cannot be
+/// timed on its own: it is part of `connect_ms` and `tls_ms` is never set.
+#[typeshare]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct HttpTimings {
+    /// Resolving the host name
+    pub dns_ms: Option<f64>,
+    /// Opening the connection: TCP connect, proxy tunnel and TLS handshake
+    pub connect_ms: Option<f64>,
+    /// The TLS handshake; unavailable, see above
+    pub tls_ms: Option<f64>,
+    /// Writing the request body, from its first to its last byte handed to
+    /// the connection; not set for requests without a body
+    pub request_write_ms: Option<f64>,
+    /// Sending the request headers and waiting for the response headers
+    pub wait_ms: f64,
+    /// Time to first byte, from the start until the response headers arrived
+    pub ttfb_ms: f64,
+    /// Reading the body; not set for streamed responses
+    pub download_ms: Option<f64>,
+    pub total_ms: f64,
+    /// Whether a pooled connection was reused
+    pub reused_connection: bool,
+    /// Approximate size of the request: request line, headers and body
+    pub request_bytes: u128,
+    /// Size of the response: status line, headers and the body as received
+    pub response_bytes: u128,
+}
+
+impl HttpTimings {
+    /// The timings recorded by `traced` up to the response headers, or
+    /// `None` for responses it did not send.
+    pub fn of(resp: &Response) -> Option<Self> {
+        let recorded = resp.extensions().get::<RecordedTimings>()?;
+        let dns = recorded.phases.dns;
+        let connect = recorded
+            .phases
+            .connect
+            .map(|connect| connect.saturating_sub(dns.unwrap_or_default()));
+        let write = recorded.phases.write;
+        let ttfb = recorded.ttfb;
+        let wait = ttfb
+            .saturating_sub(dns.unwrap_or_default())
+            .saturating_sub(connect.unwrap_or_default())
+            .saturating_sub(write.unwrap_or_default());
+
+        let status_line = format!("HTTP/1.1 {}\r\n", resp.status()).len();
+        let headers: usize = response_headers(resp)
+            .iter()
+            .map(|header| header.name.len() + header.value.len() + 4)
+            .sum();
+        Some(Self {
+            dns_ms: dns.map(millis),
+            connect_ms: connect.map(millis),
+            tls_ms: None,
+            request_write_ms: write.map(millis),
+            wait_ms: millis(wait),
+            ttfb_ms: millis(ttfb),
+            download_ms: None,
+            total_ms: millis(ttfb),
+            reused_connection: recorded.phases.connect.is_none(),
+            request_bytes: recorded.request_bytes.into(),
+            response_bytes: (status_line + headers + 2) as u128,
+        })
+    }
+
+    /// Adds reading `body_bytes` of body in `download`.
+    pub fn finish(&mut self, download: Duration, body_bytes: u64) {
+        self.download_ms = Some(millis(download));
+        self.total_ms += millis(download);
+        self.response_bytes += u128::from(body_bytes);
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Phases recorded by the resolver and connector of the client while a
+/// request is being sent
+#[derive(Debug, Clone, Copy, Default)]
+struct Phases {
+    dns: Option<Duration>,
+    connect: Option<Duration>,
+    write: Option<Duration>,
+}
+
+tokio::task_local! {
+    static PHASES: Arc<Mutex<Phases>>;
+}
+
+/// Adds to a phase of the request being sent by the current task.
+/// Connections opened in the background for the pool are not recorded.
+fn record(phase: fn(&mut Phases) -> &mut Option<Duration>, elapsed: Duration) {
+    let _ = PHASES.try_with(|phases| add(phases, phase, elapsed));
+}
+
+fn add(phases: &Mutex<Phases>, phase: fn(&mut Phases) -> &mut Option<Duration>, elapsed: Duration) {
+    let mut phases = phases.lock().expect("request phases poisoned");
+    let phase = phase(&mut phases);
+    *phase = Some(phase.unwrap_or_default() + elapsed);
+}
+
+/// Timings of a response, kept in its extensions until `HttpTimings::of`
+#[derive(Debug, Clone, Copy)]
+pub struct RecordedTimings {
+    phases: Phases,
+    ttfb: Duration,
+    request_bytes: u64,
+}
+
+impl RecordedTimings {
+    /// The timings `traced` recorded for a response.
+    pub fn of(resp: &Response) -> Option<Self> {
+        resp.extensions().get::<Self>().copied()
+    }
+
+    /// Adds these timings, of a response that redirected to `resp`, to
+    /// those of `resp`.
+    pub fn add_to(self, resp: &mut Response) {
+        let Some(timings) = resp.extensions_mut().get_mut::<Self>() else {
+            return;
+        };
+        for (phase, earlier) in [
+            (&mut timings.phases.dns, self.phases.dns),
+            (&mut timings.phases.connect, self.phases.connect),
+            (&mut timings.phases.write, self.phases.write),
+        ] {
+            if let Some(earlier) = earlier {
+                *phase = Some(phase.unwrap_or_default() + earlier);
+            }
+        }
+        timings.ttfb += self.ttfb;
+        timings.request_bytes += self.request_bytes;
+    }
+}
+
+/// Sends a request with `client`, recording the time spent in each phase
+/// until the response headers arrive. The timings are read with
+/// `HttpTimings::of`.
+pub async fn traced(client: &reqwest::Client, mut req: Request) -> reqwest::Result<Response> {
+    let request_bytes = request_size(&req);
+    let phases = Arc::new(Mutex::new(Phases::default()));
+    // The body is written by the connection's task, outside of `PHASES`.
+    // Wrapped bodies cannot be sent again, which is why `send_http`
+    // follows redirects itself.
+    if let Some(body) = req.body_mut().take() {
+        *req.body_mut() = Some(reqwest::Body::wrap(TimedBody {
+            inner: body,
+            phases: phases.clone(),
+            started: None,
+            finished: false,
+        }));
+    }
+    let start = Instant::now();
+    let mut resp = PHASES.scope(phases.clone(), client.execute(req)).await?;
+    let phases = *phases.lock().expect("request phases poisoned");
+    resp.extensions_mut().insert(RecordedTimings {
+        phases,
+        ttfb: start.elapsed(),
+        request_bytes,
+    });
+    Ok(resp)
+}
+
+/// Size of the request as HTTP/1.1 text. Headers added while sending,
+/// such as `Host`, are not known here.
+fn request_size(req: &Request) -> u64 {
+    let target = match req.url().query() {
+        Some(query) => req.url().path().len() + query.len() + 1,
+        None => req.url().path().len(),
+    };
+    let request_line = req.method().as_str().len() + target + " HTTP/1.1\r\n".len() + 1;
+    let headers: usize = req
+        .headers()
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + 4)
+        .sum();
+    // Streamed bodies, such as files, are counted by their `Content-Length`.
+    let body = match req.body().and_then(|body| body.as_bytes()) {
+        Some(bytes) => bytes.len(),
+        None => req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok()?.parse().ok())
+            .unwrap_or_default(),
+    };
+    (request_line + headers + 2 + body) as u64
+}
+
+/// Request body recording the time from its first to its last frame being
+/// polled by the connection as the `write` phase
+struct TimedBody {
+    inner: reqwest::Body,
+    phases: Arc<Mutex<Phases>>,
+    started: Option<Instant>,
+    finished: bool,
+}
+
+impl Body for TimedBody {
+    type Data = <reqwest::Body as Body>::Data;
+    type Error = <reqwest::Body as Body>::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        let started = *this.started.get_or_insert_with(Instant::now);
+        let frame = Pin::new(&mut this.inner).poll_frame(cx);
+        // Connections stop polling once the body reports its end.
+        let ended = match &frame {
+            Poll::Ready(None) => true,
+            Poll::Ready(Some(_)) => this.inner.is_end_stream(),
+            Poll::Pending => false,
+        };
+        if ended && !this.finished {
+            this.finished = true;
+            add(&this.phases, |phases| &mut phases.write, started.elapsed());
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// DNS resolver of the HTTP clients. Resolves with the system resolver,
+/// like reqwest's default one, and records the time taken. Hosts in
+/// `overrides` resolve to their address without a lookup.
+pub struct TimedResolver {
+    overrides: HashMap<String, SocketAddr>,
+}
+
+impl TimedResolver {
+    /// A resolver answering for the hosts in `overrides`, keyed by
+    /// lowercase host name.
+    pub fn new(overrides: HashMap<String, SocketAddr>) -> Self {
+        Self { overrides }
+    }
+}
+
+impl Resolve for TimedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        if let Some(&addr) = self.overrides.get(&name.as_str().to_ascii_lowercase()) {
+            return Box::pin(async move { Ok(Box::new(std::iter::once(addr)) as Addrs) });
+        }
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let start = Instant::now();
+            let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            record(|phases| &mut phases.dns, start.elapsed());
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Connector layer of the HTTP clients recording the time taken to open
+/// connections
+#[derive(Debug, Clone, Copy)]
+pub struct TimedConnectLayer;
+
+impl<S> Layer<S> for TimedConnectLayer {
+    type Service = TimedConnect<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TimedConnect(inner)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TimedConnect<S>(S);
+
+impl<S, Target> Service<Target> for TimedConnect<S>
+where
+    S: Service<Target>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: Target) -> Self::Future {
+        let connecting = self.0.call(target);
+        Box::pin(async move {
+            let start = Instant::now();
+            let conn = connecting.await?;
+            record(|phases| &mut phases.connect, start.elapsed());
+            Ok(conn)
+        })
+    }
+}
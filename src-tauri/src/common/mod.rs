@@ -1,3 +1,23 @@
+pub mod client_certificate;
+pub mod cookie_jar;
+pub mod digest_auth;
+pub mod endpoint_request;
+pub mod graphql_document;
+pub mod graphql_introspection;
+pub mod graphql_multipart;
+pub mod graphql_sse;
+pub mod graphql_subscription;
+pub mod graphql_upload;
 pub mod http_client;
 pub mod http_method;
+pub mod http_timing;
+pub mod oauth2;
 pub mod proxy_http;
+pub mod request_body;
+pub mod request_registry;
+pub mod request_signing;
+pub mod response_body;
+pub mod retry;
+pub mod secret_vault;
+pub mod sse;
+pub mod token_script;
@@ -0,0 +1,769 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use log::{debug, warn};
+use rand::RngCore;
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+
+use crate::common::endpoint_request::endpoint_request_options;
+use crate::common::http_client::{client_for, error_chain, ClientKind};
+use crate::common::secret_vault;
+use crate::common::token_script::{token_script, TokenScriptCache};
+use crate::database::entities::endpoint_entity::{AuthConfig, AuthType, Endpoint};
+use crate::database::repositories::EndpointRepository;
+
+/// Tokens are renewed this long before they expire, so they do not run
+/// out while a request is in flight.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+/// How long the user has to complete the authorization in the browser
+const AUTHORIZATION_TIMEOUT: Duration = Duration::from_secs(300);
+/// How long a connection to the redirect listener may take to send its
+/// request
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// Path of the loopback redirect URI, `http://127.0.0.1:<port>/callback`
+const REDIRECT_PATH: &str = "/callback";
+
+/// Successful token endpoint response (RFC 6749, section 5.1)
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+    refresh_token: Option<String>,
+}
+
+/// Error response of the token endpoint (RFC 6749, section 5.2)
+#[derive(Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+struct CachedToken {
+    /// Client settings the token was issued for; a change discards it
+    issued_for: ClientSettings,
+    access_token: String,
+    refresh_token: Option<String>,
+    /// `None` when the server did not say when the token expires
+    expires_at: Option<Instant>,
+}
+
+impl CachedToken {
+    fn is_fresh(&self) -> bool {
+        self.expires_at
+            .is_none_or(|expires_at| Instant::now() + EXPIRY_MARGIN < expires_at)
+    }
+
+    /// Writes the token into the fields of `auth` that persist it.
+    fn store_in(&self, auth: &mut AuthConfig) {
+        auth.token = Some(self.access_token.clone());
+        auth.oauth_refresh_token = self.refresh_token.clone();
+        auth.oauth_token_expires_at = self.expires_at.map(|expires_at| {
+            let remaining = expires_at.saturating_duration_since(Instant::now());
+            (Utc::now() + remaining).to_rfc3339()
+        });
+    }
+}
+
+/// How the endpoint obtains its tokens
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Grant {
+    /// The backend requests tokens for the client itself
+    ClientCredentials,
+    /// The user signs in with `authorize_endpoint`; the backend only
+    /// refreshes the resulting token
+    AuthorizationCode,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+struct ClientSettings {
+    grant: Grant,
+    token_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    scope: Option<String>,
+}
+
+impl ClientSettings {
+    /// Settings of an endpoint whose token is obtained by the backend: an
+    /// OAuth2 endpoint with a token URL and client id. Endpoints with an
+    /// authorization URL use the authorization-code grant.
+    fn of(auth: &AuthConfig) -> Option<Self> {
+        if auth.auth_type != AuthType::OAuth2 {
+            return None;
+        }
+        let grant = match non_empty(&auth.oauth_authorize_url) {
+            Some(_) => Grant::AuthorizationCode,
+            None => Grant::ClientCredentials,
+        };
+        Some(ClientSettings {
+            grant,
+            token_url: non_empty(&auth.oauth_token_url)?,
+            client_id: non_empty(&auth.oauth_client_id)?,
+            client_secret: non_empty(&auth.oauth_client_secret),
+            scope: non_empty(&auth.oauth_scope),
+        })
+    }
+
+    /// The token saved on the endpoint by a previous authorization.
+    fn stored_token(&self, auth: &AuthConfig) -> Option<CachedToken> {
+        let expires_at = match &auth.oauth_token_expires_at {
+            Some(expires_at) => {
+                let expires_at = DateTime::parse_from_rfc3339(expires_at).ok()?;
+                let remaining = (expires_at.with_timezone(&Utc) - Utc::now())
+                    .to_std()
+                    .unwrap_or_default();
+                Some(Instant::now() + remaining)
+            }
+            None => None,
+        };
+        Some(CachedToken {
+            issued_for: self.clone(),
+            access_token: non_empty(&auth.token)?,
+            refresh_token: non_empty(&auth.oauth_refresh_token),
+            expires_at,
+        })
+    }
+}
+
+fn non_empty(value: &Option<String>) -> Option<String> {
+    value.clone().filter(|v| !v.trim().is_empty())
+}
+
+/// Token of one endpoint. Its lock is held across token requests so
+/// concurrent requests to the endpoint share a single token fetch, without
+/// waiting for other endpoints.
+type TokenSlot = Arc<Mutex<Option<CachedToken>>>;
+
+/// Access tokens obtained for OAuth2 endpoints, keyed by endpoint id,
+/// along with the tokens produced by token scripts. Stored in Tauri state.
+#[derive(Default)]
+pub struct OAuthTokenCache {
+    tokens: std::sync::Mutex<HashMap<String, TokenSlot>>,
+    scripts: TokenScriptCache,
+}
+
+impl OAuthTokenCache {
+    /// Returns the endpoint with `auth.token` set to a valid access token
+    /// when its token is obtained by the backend. Cached tokens are reused
+    /// until they expire; then the refresh-token grant is tried before
+    /// falling back to the client-credentials grant. Tokens refreshed for
+    /// the authorization-code grant are saved to the endpoint, since the
+    /// server may have rotated the refresh token.
+    ///
+    /// Endpoints with a `token_script` get the token it returns instead, see
+    /// `TokenScriptCache::authorize`.
+    pub async fn authorize(
+        &self,
+        pool: &SqlitePool,
+        endpoint: &Endpoint,
+    ) -> Result<Endpoint, String> {
+        secret_vault::ensure_unlocked(endpoint)?;
+        let options = endpoint_request_options(endpoint);
+        let mut endpoint = endpoint.clone();
+        let key = endpoint.id.to_string();
+        let Some(auth) = endpoint.auth.as_mut() else {
+            return Ok(endpoint);
+        };
+        self.scripts.authorize(&key, auth, options.clone()).await?;
+        let Some(settings) = ClientSettings::of(auth) else {
+            return Ok(endpoint);
+        };
+
+        let slot = self.slot(&key);
+        let mut slot = slot.lock().await;
+        if slot
+            .as_ref()
+            .is_some_and(|token| token.issued_for != settings)
+        {
+            *slot = None;
+        }
+        if slot.is_none() && settings.grant == Grant::AuthorizationCode {
+            *slot = settings.stored_token(auth);
+        }
+
+        // The cached token stays in place until a new one is obtained, so a
+        // failed request keeps its refresh token for the next attempt.
+        let access_token = match slot.as_ref() {
+            Some(token) if token.is_fresh() => token.access_token.clone(),
+            cached => {
+                let refresh_token = cached
+                    .and_then(|token| token.refresh_token.clone())
+                    .or_else(|| non_empty(&auth.oauth_refresh_token));
+                let client = client_for(ClientKind::Standard, options.as_ref())?;
+                let token = obtain_token(&client, settings, refresh_token).await?;
+                if token.issued_for.grant == Grant::AuthorizationCode {
+                    token.store_in(auth);
+                    if let Err(e) = save_tokens(pool, &key, auth).await {
+                        warn!("Failed to save refreshed OAuth2 tokens: {}", e);
+                    }
+                }
+                let access_token = token.access_token.clone();
+                *slot = Some(token);
+                access_token
+            }
+        };
+
+        auth.token = Some(access_token);
+        Ok(endpoint)
+    }
+
+    /// Forgets the access token of an endpoint, e.g. after the server
+    /// rejected it, keeping the refresh token for the next request.
+    pub async fn invalidate(&self, endpoint_id: &str) {
+        let slot = self.lock_tokens().get(endpoint_id).cloned();
+        if let Some(slot) = slot {
+            if let Some(token) = slot.lock().await.as_mut() {
+                token.expires_at = Some(Instant::now());
+            }
+        }
+        self.scripts.invalidate(endpoint_id).await;
+    }
+
+    /// Forgets all tokens, e.g. when endpoint secrets are locked.
+    pub async fn clear(&self) {
+        self.lock_tokens().clear();
+        self.scripts.clear().await;
+    }
+
+    fn slot(&self, endpoint_id: &str) -> TokenSlot {
+        self.lock_tokens()
+            .entry(endpoint_id.to_string())
+            .or_default()
+            .clone()
+    }
+
+    fn lock_tokens(&self) -> std::sync::MutexGuard<'_, HashMap<String, TokenSlot>> {
+        self.tokens.lock().expect("OAuth2 token cache poisoned")
+    }
+
+    /// Whether requests to the endpoint carry a token obtained by the
+    /// backend, and may therefore be retried with a new one.
+    pub fn manages(endpoint: &Endpoint) -> bool {
+        endpoint
+            .auth
+            .as_ref()
+            .is_some_and(|auth| ClientSettings::of(auth).is_some() || token_script(auth).is_some())
+    }
+
+    /// Runs the interactive authorization-code flow with PKCE for an
+    /// endpoint and saves the resulting tokens to it.
+    ///
+    /// The authorization URL is passed to `open_url` for the user to sign
+    /// in with their browser, which then redirects to a temporary listener
+    /// on `127.0.0.1` (`oauth_redirect_port`, or any free port). The code
+    /// it receives is exchanged at `oauth_token_url`.
+    pub async fn authorize_interactively<F>(
+        &self,
+        pool: &SqlitePool,
+        endpoint: &Endpoint,
+        open_url: F,
+    ) -> Result<(), String>
+    where
+        F: FnOnce(&str) -> Result<(), String>,
+    {
+        secret_vault::ensure_unlocked(endpoint)?;
+        let mut auth = endpoint
+            .auth
+            .clone()
+            .ok_or_else(|| "Endpoint has no authentication configured".to_string())?;
+        let settings = ClientSettings::of(&auth)
+            .filter(|settings| settings.grant == Grant::AuthorizationCode)
+            .ok_or_else(|| {
+                "The authorization-code flow needs an OAuth2 authorization URL, \
+                 token URL and client ID"
+                    .to_string()
+            })?;
+        let authorize_url = non_empty(&auth.oauth_authorize_url).unwrap_or_default();
+
+        let listener = TcpListener::bind(("127.0.0.1", auth.oauth_redirect_port.unwrap_or(0)))
+            .await
+            .map_err(|e| format!("Failed to listen for the OAuth2 redirect: {}", e))?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        let redirect_uri = format!("http://127.0.0.1:{}{}", port, REDIRECT_PATH);
+
+        let verifier = random_token(32);
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let state = random_token(16);
+
+        let mut url = Url::parse(&authorize_url)
+            .map_err(|e| format!("Invalid OAuth2 authorization URL: {}", e))?;
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("response_type", "code")
+                .append_pair("client_id", &settings.client_id)
+                .append_pair("redirect_uri", &redirect_uri)
+                .append_pair("code_challenge", &challenge)
+                .append_pair("code_challenge_method", "S256")
+                .append_pair("state", &state);
+            if let Some(scope) = &settings.scope {
+                query.append_pair("scope", scope);
+            }
+            if let Some(audience) = non_empty(&auth.oauth_audience) {
+                query.append_pair("audience", &audience);
+            }
+        }
+        open_url(url.as_str())?;
+
+        let code = tokio::time::timeout(
+            AUTHORIZATION_TIMEOUT,
+            receive_authorization_code(&listener, &state),
+        )
+        .await
+        .map_err(|_| "Timed out waiting for the OAuth2 authorization".to_string())??;
+
+        let options = endpoint_request_options(endpoint);
+        let client = client_for(ClientKind::Standard, options.as_ref())?;
+        let params = vec![
+            ("grant_type", "authorization_code".to_string()),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("code_verifier", verifier),
+        ];
+        let response = request_token(&client, &settings, params).await?;
+        debug!("Obtained OAuth2 token from {}", settings.token_url);
+        let refresh_token = response.refresh_token.clone();
+        let token = cached_token(settings, response, refresh_token);
+
+        token.store_in(&mut auth);
+        let key = endpoint.id.to_string();
+        save_tokens(pool, &key, &auth)
+            .await
+            .map_err(|e| format!("Failed to save OAuth2 tokens: {}", e))?;
+
+        *self.slot(&key).lock().await = Some(token);
+        Ok(())
+    }
+}
+
+/// Saves the tokens stored in `auth`, leaving the rest of the endpoint as
+/// it is now rather than as it was when the token request started.
+async fn save_tokens(
+    pool: &SqlitePool,
+    endpoint_id: &str,
+    auth: &AuthConfig,
+) -> anyhow::Result<()> {
+    EndpointRepository::update_oauth_tokens(
+        pool,
+        endpoint_id,
+        auth.token.clone(),
+        auth.oauth_refresh_token.clone(),
+        auth.oauth_token_expires_at.clone(),
+    )
+    .await
+}
+
+async fn obtain_token(
+    client: &reqwest::Client,
+    settings: ClientSettings,
+    refresh_token: Option<String>,
+) -> Result<CachedToken, String> {
+    if let Some(refresh_token) = refresh_token {
+        let params = vec![
+            ("grant_type", "refresh_token".to_string()),
+            ("refresh_token", refresh_token.clone()),
+        ];
+        match request_token(client, &settings, params).await {
+            Ok(response) => {
+                debug!("Refreshed OAuth2 token from {}", settings.token_url);
+                // Servers may keep the refresh token without reissuing it.
+                let refresh_token = response.refresh_token.clone().or(Some(refresh_token));
+                return Ok(cached_token(settings, response, refresh_token));
+            }
+            Err(e) if settings.grant == Grant::AuthorizationCode => {
+                return Err(format!(
+                    "OAuth2 authorization expired, authorize the endpoint again: {}",
+                    e
+                ))
+            }
+            Err(e) => warn!(
+                "OAuth2 refresh failed, requesting a new token instead: {}",
+                e
+            ),
+        }
+    }
+
+    if settings.grant == Grant::AuthorizationCode {
+        return Err(
+            "OAuth2 authorization is missing or expired, authorize the endpoint again".to_string(),
+        );
+    }
+
+    let mut params = vec![("grant_type", "client_credentials".to_string())];
+    if let Some(scope) = &settings.scope {
+        params.push(("scope", scope.clone()));
+    }
+    let response = request_token(client, &settings, params).await?;
+    debug!("Obtained OAuth2 token from {}", settings.token_url);
+    let refresh_token = response.refresh_token.clone();
+    Ok(cached_token(settings, response, refresh_token))
+}
+
+fn cached_token(
+    settings: ClientSettings,
+    response: TokenResponse,
+    refresh_token: Option<String>,
+) -> CachedToken {
+    CachedToken {
+        issued_for: settings,
+        access_token: response.access_token,
+        refresh_token,
+        expires_at: response
+            .expires_in
+            .map(|seconds| Instant::now() + Duration::from_secs(seconds)),
+    }
+}
+
+/// Posts a grant to the token endpoint. Confidential clients authenticate
+/// with HTTP Basic authentication (`client_secret_basic`), public clients
+/// send their `client_id` in the body.
+async fn request_token(
+    client: &reqwest::Client,
+    settings: &ClientSettings,
+    mut params: Vec<(&str, String)>,
+) -> Result<TokenResponse, String> {
+    let mut builder = client
+        .post(&settings.token_url)
+        .header("Accept", "application/json");
+    match &settings.client_secret {
+        Some(secret) => builder = builder.basic_auth(&settings.client_id, Some(secret)),
+        None => params.push(("client_id", settings.client_id.clone())),
+    }
+
+    let resp = builder
+        .form(&params)
+        .send()
+        .await
+        .map_err(|e| format!("OAuth2 token request failed: {}", error_chain(&e)))?;
+
+    let status = resp.status();
+    let body = resp
+        .text()
+        .await
+        .map_err(|e| format!("Failed to read OAuth2 token response: {}", e))?;
+
+    if !status.is_success() {
+        return Err(match serde_json::from_str::<TokenErrorResponse>(&body) {
+            Ok(error) => format!(
+                "OAuth2 token request rejected: {}{}",
+                error.error,
+                error
+                    .error_description
+                    .map(|description| format!(" - {}", description))
+                    .unwrap_or_default()
+            ),
+            Err(_) => format!("OAuth2 token request failed: {} - {}", status, body),
+        });
+    }
+
+    serde_json::from_str(&body).map_err(|e| format!("Invalid OAuth2 token response: {}", e))
+}
+
+/// Random URL-safe string of `len` bytes of entropy, used for the PKCE
+/// verifier and the state parameter.
+fn random_token(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Serves the loopback redirect until it carries the authorization
+/// response, answering the browser with a page to close the tab.
+///
+/// Connections are read concurrently, each for at most
+/// `REQUEST_READ_TIMEOUT`, since browsers open connections they may never
+/// send a request on.
+async fn receive_authorization_code(listener: &TcpListener, state: &str) -> Result<String, String> {
+    let mut connections = FuturesUnordered::new();
+    loop {
+        let (mut stream, target) = tokio::select! {
+            accepted = listener.accept() => {
+                let (mut stream, _) = accepted
+                    .map_err(|e| format!("Failed to accept the OAuth2 redirect: {}", e))?;
+                connections.push(async move {
+                    let target = tokio::time::timeout(
+                        REQUEST_READ_TIMEOUT,
+                        read_request_target(&mut stream),
+                    )
+                    .await;
+                    (stream, target.ok().flatten())
+                });
+                continue;
+            }
+            Some(connection) = connections.next() => connection,
+        };
+
+        let Some(target) = target else {
+            continue;
+        };
+        let Ok(url) = Url::parse(&format!("http://127.0.0.1{}", target)) else {
+            continue;
+        };
+        if url.path() != REDIRECT_PATH {
+            // Browsers also ask for things like /favicon.ico.
+            let _ = respond(&mut stream, "404 Not Found", "Not found").await;
+            continue;
+        }
+
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        if params.get("state").map(String::as_str) != Some(state) {
+            let _ = respond(&mut stream, "400 Bad Request", "Invalid state").await;
+            continue;
+        }
+
+        if let Some(error) = params.get("error") {
+            let _ = respond(
+                &mut stream,
+                "200 OK",
+                "Authorization failed. You can close this window.",
+            )
+            .await;
+            return Err(format!(
+                "OAuth2 authorization failed: {}{}",
+                error,
+                params
+                    .get("error_description")
+                    .map(|description| format!(" - {}", description))
+                    .unwrap_or_default()
+            ));
+        }
+
+        let Some(code) = params.get("code") else {
+            let _ = respond(&mut stream, "400 Bad Request", "Missing code").await;
+            continue;
+        };
+        let _ = respond(
+            &mut stream,
+            "200 OK",
+            "Authorization complete. You can close this window and return to Query Box.",
+        )
+        .await;
+        return Ok(code.clone());
+    }
+}
+
+/// Reads the head of an HTTP request and returns its request target.
+async fn read_request_target(stream: &mut TcpStream) -> Option<String> {
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") && head.len() < 16 * 1024 {
+        let read = stream.read(&mut buffer).await.ok()?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next()?.split_whitespace();
+    match (request_line.next()?, request_line.next()) {
+        ("GET", Some(target)) => Some(target.to_string()),
+        _ => None,
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, message: &str) -> std::io::Result<()> {
+    let body = format!(
+        "<!doctype html><html><head><meta charset=\"utf-8\"><title>Query Box</title></head>\
+         <body style=\"font-family: sans-serif\"><p>{}</p></body></html>",
+        message
+    );
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    type Requests = Arc<std::sync::Mutex<Vec<String>>>;
+
+    /// Token endpoint answering one request with each of `responses` in
+    /// turn, recording the form bodies it receives.
+    async fn token_server(responses: Vec<(u16, &'static str)>) -> (String, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        let requests = Requests::default();
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let form = read_form(&mut stream).await;
+                received.lock().unwrap().push(form);
+                let response = format!(
+                    "HTTP/1.1 {} Token\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    async fn read_form(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1024];
+        loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            assert!(read > 0, "token request ended early");
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request);
+            let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                continue;
+            };
+            let length = head
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                .map_or(0, |(_, value)| value.trim().parse().unwrap());
+            if body.len() >= length {
+                return body.to_string();
+            }
+        }
+    }
+
+    fn endpoint(token_url: &str) -> Endpoint {
+        serde_json::from_value(json!({
+            "id": uuid::Uuid::new_v4(),
+            "name": "api",
+            "endpoint_type": "graphql",
+            "url": "http://127.0.0.1/graphql",
+            "status": "active",
+            "auth": {
+                "auth_type": "oauth2",
+                "oauth_token_url": token_url,
+                "oauth_client_id": "client",
+                "oauth_client_secret": "secret",
+            },
+            "config": null,
+            "headers": null,
+            "favorite": false,
+            "tags": null,
+            "created_at": "",
+            "updated_at": "",
+        }))
+        .unwrap()
+    }
+
+    fn access_token(result: Result<Endpoint, String>) -> String {
+        result.unwrap().auth.unwrap().token.unwrap()
+    }
+
+    /// Client-credentials endpoints never touch the database.
+    fn pool() -> SqlitePool {
+        SqlitePool::connect_lazy("sqlite::memory:").unwrap()
+    }
+
+    #[tokio::test]
+    async fn keeps_the_refresh_token_when_renewing_fails() {
+        let (url, requests) = token_server(vec![
+            (
+                200,
+                r#"{"access_token":"a1","expires_in":1,"refresh_token":"r1"}"#,
+            ),
+            (503, "unavailable"),
+            (503, "unavailable"),
+            (200, r#"{"access_token":"a2","expires_in":3600}"#),
+        ])
+        .await;
+        let (cache, pool, endpoint) = (OAuthTokenCache::default(), pool(), endpoint(&url));
+
+        assert_eq!(access_token(cache.authorize(&pool, &endpoint).await), "a1");
+        // `a1` expires within the margin; refreshing and the fallback fail.
+        assert!(cache.authorize(&pool, &endpoint).await.is_err());
+        assert_eq!(access_token(cache.authorize(&pool, &endpoint).await), "a2");
+        assert_eq!(access_token(cache.authorize(&pool, &endpoint).await), "a2");
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                "grant_type=client_credentials",
+                "grant_type=refresh_token&refresh_token=r1",
+                "grant_type=client_credentials",
+                "grant_type=refresh_token&refresh_token=r1",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn shares_one_token_request_per_endpoint() {
+        // The server answers once; a second request would be refused.
+        let (url, requests) = token_server(vec![(200, r#"{"access_token":"a"}"#)]).await;
+        let (cache, pool, endpoint) = (OAuthTokenCache::default(), pool(), endpoint(&url));
+
+        let (first, second) = tokio::join!(
+            cache.authorize(&pool, &endpoint),
+            cache.authorize(&pool, &endpoint)
+        );
+        assert_eq!(access_token(first), "a");
+        assert_eq!(access_token(second), "a");
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_wait_for_token_requests_of_other_endpoints() {
+        // Connections to this listener are never answered.
+        let stalled = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stalled = endpoint(&format!("http://{}/token", stalled.local_addr().unwrap()));
+        let (url, _) = token_server(vec![(200, r#"{"access_token":"b"}"#)]).await;
+        let (cache, pool, endpoint) = (OAuthTokenCache::default(), pool(), endpoint(&url));
+
+        tokio::select! {
+            _ = cache.authorize(&pool, &stalled) => panic!("the stalled token request completed"),
+            result = async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                cache.authorize(&pool, &endpoint).await
+            } => assert_eq!(access_token(result), "b"),
+        }
+    }
+
+    async fn get(addr: std::net::SocketAddr, target: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", target);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn receives_the_code_while_other_connections_stay_idle() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let browser = async {
+            let _idle = TcpStream::connect(addr).await.unwrap();
+            let rejected = get(addr, "/callback?state=other&code=stolen").await;
+            assert!(rejected.starts_with("HTTP/1.1 400 Bad Request"));
+            let missing = get(addr, "/favicon.ico").await;
+            assert!(missing.starts_with("HTTP/1.1 404 Not Found"));
+            get(addr, "/callback?state=expected&code=abc").await
+        };
+
+        let (code, response) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(receive_authorization_code(&listener, "expected"), browser)
+        })
+        .await
+        .expect("an idle connection blocked the redirect");
+        assert_eq!(code.unwrap(), "abc");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+    }
+}
@@ -1,83 +1,752 @@
-use reqwest::Method;
+use reqwest::header::{
+    HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE,
+    COOKIE, LOCATION, PROXY_AUTHORIZATION, REFERER, TRANSFER_ENCODING, WWW_AUTHENTICATE,
+};
+use reqwest::{Method, Response, StatusCode, Url, Version};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::time::Instant;
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use typeshare::typeshare;
 
-use crate::common::http_client::HTTP_CLIENT;
+use crate::common::cookie_jar::CookieJar;
+use crate::common::http_client::{client_for, ClientKind, RequestOptions};
+use crate::common::http_timing::{traced, HttpTimings, RecordedTimings};
+use crate::common::request_body::HttpBody;
+use crate::common::request_signing::RequestSigning;
+use crate::common::response_body::{
+    read_body, save_body, BodyEncoding, BodySize, ContentCoding, DEFAULT_MAX_BODY_BYTES,
+};
+use crate::common::retry::{with_retries, RequestAttempt};
 
-#[derive(Deserialize)]
+/// A header of a request or response. Headers are kept as an ordered list
+/// so that repeated ones, such as `Set-Cookie`, are all preserved.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[typeshare]
+pub struct HttpHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl HttpHeader {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
+
+impl From<(String, String)> for HttpHeader {
+    fn from((name, value): (String, String)) -> Self {
+        Self { name, value }
+    }
+}
+
+#[derive(Clone, Deserialize)]
 #[typeshare]
 pub struct HttpRequest {
     pub method: String,
     pub url: String,
-    pub headers: Option<HashMap<String, String>>,
-    pub body: Option<String>,
+    /// Headers in the order they are sent; a name may appear several times
+    pub headers: Option<Vec<HttpHeader>>,
+    /// Body of the request, which also determines its default `Content-Type`
+    pub body: Option<HttpBody>,
+    /// Network options; unset fields use the built-in defaults
+    pub options: Option<RequestOptions>,
+    /// Endpoint whose headers, authentication and options are applied,
+    /// see `apply_endpoint`
+    pub endpoint_id: Option<String>,
+    /// Signature added once the request is assembled, set from the
+    /// endpoint's authentication
+    #[serde(skip)]
+    pub signing: Option<RequestSigning>,
+    /// Cookie jar of the endpoint, whose cookies are sent and updated
+    #[serde(skip)]
+    pub cookie_jar: Option<CookieJar>,
 }
 
 #[derive(Serialize)]
 #[typeshare]
 pub struct HttpResponse {
     pub status_code: u16,
-    pub headers: HashMap<String, String>,
+    /// Reason phrase of the status line, or the standard one for the
+    /// status code when the server sent none (always the case over HTTP/2)
+    pub status_text: String,
+    /// Protocol of the response, e.g. `HTTP/1.1` or `HTTP/2.0`
+    pub http_version: String,
+    /// Headers in the order received, see `response_headers`
+    pub headers: Vec<HttpHeader>,
+    /// The body as text, base64 or a file path, see `body_encoding`
     pub body: String,
+    pub body_encoding: BodyEncoding,
+    /// `Content-Encoding` of the body as received, e.g. `gzip`
+    pub content_encoding: Option<String>,
+    /// Size of the body as received, compressed when `content_encoding`
+    /// is set; not set for streamed responses
+    pub received_body_bytes: Option<u128>,
+    /// Size of the body once decompressed, see
+    /// `RequestOptions.accept_encoding`; the same as `received_body_bytes`
+    /// when the body was not decompressed
+    pub decoded_body_bytes: Option<u128>,
     pub duration_ms: u128,
+    /// Time spent in each phase of the exchange and its size
+    pub timings: Option<HttpTimings>,
+    /// Every attempt at sending the request when a retry policy applies
+    pub attempts: Vec<RequestAttempt>,
+}
+
+impl HttpResponse {
+    /// The status line and headers of a response, with an empty body, for
+    /// callers that fill in the body once it has been read.
+    pub fn head(resp: &Response) -> Self {
+        let status_text = match resp.extensions().get::<hyper::ext::ReasonPhrase>() {
+            Some(reason) => String::from_utf8_lossy(reason.as_bytes()).into_owned(),
+            None => resp
+                .status()
+                .canonical_reason()
+                .unwrap_or_default()
+                .to_string(),
+        };
+        Self {
+            status_code: resp.status().as_u16(),
+            status_text,
+            http_version: http_version(resp.version()).to_string(),
+            headers: response_headers(resp),
+            body: String::new(),
+            body_encoding: BodyEncoding::Text,
+            content_encoding: resp
+                .headers()
+                .get(CONTENT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            received_body_bytes: None,
+            decoded_body_bytes: None,
+            duration_ms: 0,
+            timings: HttpTimings::of(resp),
+            attempts: Vec::new(),
+        }
+    }
+
+    /// Records the size of the body read in `download`.
+    fn set_body_size(&mut self, size: BodySize, download: Duration) {
+        self.received_body_bytes = Some(size.received.into());
+        self.decoded_body_bytes = Some(size.decoded.into());
+        if let Some(timings) = &mut self.timings {
+            timings.finish(download, size.received);
+        }
+    }
 }
 
 #[derive(Serialize)]
 #[typeshare]
 pub struct HttpError {
     pub message: String,
+    pub kind: HttpErrorKind,
+    /// Every attempt at sending the request when a retry policy applies
+    pub attempts: Vec<RequestAttempt>,
+    /// The request failed on the way, e.g. on a reset connection or a
+    /// timeout, so sending it again may succeed
+    #[serde(skip)]
+    pub transient: bool,
 }
 
-pub async fn proxy_http(request: HttpRequest) -> Result<HttpResponse, HttpError> {
-    let method = request.method.parse::<Method>().map_err(|e| HttpError {
-        message: format!("Invalid HTTP method: {}", e),
-    })?;
-    let mut req_builder = HTTP_CLIENT.request(method, &request.url);
-
-    if let Some(headers) = &request.headers {
-        for (key, value) in headers {
-            req_builder = req_builder.header(key, value);
+/// Lets the frontend tell a cancelled request apart from a failed one
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+#[typeshare]
+pub enum HttpErrorKind {
+    Failed,
+    Cancelled,
+}
+
+impl HttpError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            kind: HttpErrorKind::Failed,
+            attempts: Vec::new(),
+            transient: false,
+        }
+    }
+
+    /// A failure to exchange the request with the server, see `transient`
+    pub fn transient(message: impl Into<String>) -> Self {
+        Self {
+            transient: true,
+            ..Self::new(message)
         }
     }
+
+    pub fn cancelled() -> Self {
+        Self {
+            message: "Request cancelled".to_string(),
+            kind: HttpErrorKind::Cancelled,
+            attempts: Vec::new(),
+            transient: false,
+        }
+    }
+}
+
+/// Scheme of URLs that target a Unix domain socket
+const UNIX_SCHEME: &str = "unix://";
+
+/// Points a request to a URL such as `unix:///var/run/docker.sock:/v1.43/info`
+/// at the Unix domain socket it names, requesting the URL that follows the
+/// socket path after a colon (`/` when left out) with `localhost` as the
+/// host. Only requests sent by the user go through this, so that URLs of
+/// token scripts or servers cannot reach local sockets.
+pub fn use_unix_socket(request: &mut HttpRequest) {
+    let Some(target) = request.url.strip_prefix(UNIX_SCHEME) else {
+        return;
+    };
+    let (socket, path) = target.split_once(':').unwrap_or((target, ""));
+    request
+        .options
+        .get_or_insert_with(Default::default)
+        .unix_socket = Some(socket.to_string());
+    request.url = format!("http://localhost/{}", path.trim_start_matches('/'));
+}
+
+/// Sends a request with a client of the given kind configured from
+/// `request.options`, and returns the response without reading its body,
+/// for callers that consume the body as a stream.
+pub async fn send_http(kind: ClientKind, request: &HttpRequest) -> Result<Response, HttpError> {
+    // Redirects are followed by `execute` rather than the client, so that
+    // the cookie jar and the timings see every response, and bodies timed
+    // by `traced` can be sent again.
+    let mut options = request.options.clone().unwrap_or_default();
+    let max_redirects = options.redirect_limit().unwrap_or(0);
+    options.follow_redirects = Some(false);
+    let client = client_for(kind, Some(&options)).map_err(HttpError::new)?;
+    let method = request
+        .method
+        .parse::<Method>()
+        .map_err(|e| HttpError::new(format!("Invalid HTTP method: {}", e)))?;
+    let mut req_builder = client.request(method, &request.url);
+
+    for header in request.headers.iter().flatten() {
+        // The boundary of a multipart body is only known here.
+        if matches!(request.body, Some(HttpBody::Multipart(_)))
+            && header.name.eq_ignore_ascii_case("content-type")
+        {
+            continue;
+        }
+        req_builder = req_builder.header(&header.name, &header.value);
+    }
     if let Some(body) = &request.body {
-        req_builder = req_builder.body(body.clone());
+        req_builder = body.apply(req_builder).await?;
+    }
+
+    let mut req = req_builder
+        .build()
+        .map_err(|err| HttpError::new(format!("Invalid HTTP request: {}", err)))?;
+    if let Some(content_type) = request.body.as_ref().and_then(HttpBody::content_type) {
+        req.headers_mut()
+            .entry(CONTENT_TYPE)
+            .or_insert(HeaderValue::from_static(content_type));
+    }
+    let exchange = Exchange {
+        client,
+        cookie_jar: request.cookie_jar.as_ref(),
+        max_redirects,
+        own_cookies: req.headers().get_all(COOKIE).iter().cloned().collect(),
+    };
+    if let Some(cookie_jar) = exchange.cookie_jar {
+        cookie_jar
+            .add_cookies(&mut req)
+            .await
+            .map_err(HttpError::new)?;
+    }
+    let Some(signing) = &request.signing else {
+        return exchange.execute(req).await;
+    };
+    let url = req.url().clone();
+    let retry = req.try_clone();
+    sign(signing, &mut req)?;
+    let resp = exchange.execute(req).await?;
+
+    if !signing.retry_challenge(&url, &resp) {
+        return Ok(resp);
+    }
+    // Streamed bodies, such as files, cannot be sent a second time. The
+    // challenge is kept, so sending the request again answers it up front.
+    let Some(mut retry) = retry else {
+        return Err(HttpError::new(
+            "Digest authentication needs a request body that can be sent twice; \
+             send the request again to answer the server's challenge",
+        ));
+    };
+    sign(signing, &mut retry)?;
+    exchange.execute(retry).await
+}
+
+fn sign(signing: &RequestSigning, req: &mut reqwest::Request) -> Result<(), HttpError> {
+    signing
+        .sign(req)
+        .map_err(|e| HttpError::new(format!("Failed to sign request: {}", e)))
+}
+
+/// Sends the requests of one `send_http` call
+struct Exchange<'a> {
+    client: reqwest::Client,
+    cookie_jar: Option<&'a CookieJar>,
+    /// Redirects followed by `execute`
+    max_redirects: u32,
+    /// `Cookie` headers of the request itself, without those of the jar
+    own_cookies: Vec<HeaderValue>,
+}
+
+impl Exchange<'_> {
+    /// Sends a request, storing the cookies set by the response in the jar.
+    async fn execute(&self, mut req: reqwest::Request) -> Result<Response, HttpError> {
+        let mut redirects = 0;
+        let mut earlier: Option<RecordedTimings> = None;
+        loop {
+            let replay = (self.max_redirects > 0).then(|| replay(&req));
+            let mut resp = traced(&self.client, req)
+                .await
+                .map_err(|err| HttpError::transient(format!("HTTP request failed: {}", err)))?;
+            if let Some(earlier) = earlier {
+                earlier.add_to(&mut resp);
+            }
+            if let Some(cookie_jar) = self.cookie_jar {
+                cookie_jar.store(resp.url(), resp.headers()).await;
+            }
+
+            let Some(next) = replay.and_then(|replay| redirect(replay, &resp)) else {
+                return Ok(resp);
+            };
+            if redirects == self.max_redirects {
+                return Err(HttpError::new(format!(
+                    "Too many redirects, stopped after {}",
+                    redirects
+                )));
+            }
+            redirects += 1;
+            req = next;
+            if same_origin(resp.url(), req.url()) {
+                for cookie in &self.own_cookies {
+                    req.headers_mut().append(COOKIE, cookie.clone());
+                }
+            }
+            earlier = RecordedTimings::of(&resp);
+            if let Some(cookie_jar) = self.cookie_jar {
+                cookie_jar
+                    .add_cookies(&mut req)
+                    .await
+                    .map_err(HttpError::new)?;
+            }
+        }
+    }
+}
+
+/// A copy of `req` to send again when it is redirected, and whether its
+/// body was kept; streamed bodies cannot be copied.
+fn replay(req: &reqwest::Request) -> (reqwest::Request, bool) {
+    if let Some(copy) = req.try_clone() {
+        return (copy, true);
     }
+    let mut copy = reqwest::Request::new(req.method().clone(), req.url().clone());
+    *copy.headers_mut() = req.headers().clone();
+    *copy.timeout_mut() = req.timeout().copied();
+    *copy.version_mut() = req.version();
+    (copy, req.body().is_none())
+}
+
+/// The request following the redirect `resp`, built from a `replay` of the
+/// redirected request the way the client's own redirects are, or `None`
+/// when `resp` is not a redirect that can be followed. The `Cookie` header
+/// is removed so it can be rebuilt for the new URL.
+fn redirect(
+    (mut req, has_body): (reqwest::Request, bool),
+    resp: &Response,
+) -> Option<reqwest::Request> {
+    let location = resp.headers().get(LOCATION)?.to_str().ok()?;
+    let url = resp
+        .url()
+        .join(location)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))?;
+    match resp.status() {
+        StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND | StatusCode::SEE_OTHER => {
+            *req.body_mut() = None;
+            for name in [TRANSFER_ENCODING, CONTENT_TYPE, CONTENT_LENGTH] {
+                req.headers_mut().remove(name);
+            }
+            if !matches!(*req.method(), Method::GET | Method::HEAD) {
+                *req.method_mut() = Method::GET;
+            }
+        }
+        StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT if has_body => {}
+        _ => return None,
+    }
+
+    let headers = req.headers_mut();
+    headers.remove(COOKIE);
+    if !same_origin(resp.url(), &url) {
+        for name in [AUTHORIZATION, PROXY_AUTHORIZATION, WWW_AUTHENTICATE] {
+            headers.remove(name);
+        }
+    }
+    // Like browsers, do not tell plain HTTP servers about HTTPS URLs.
+    if resp.url().scheme() == "https" && url.scheme() == "http" {
+        headers.remove(REFERER);
+    } else {
+        let mut referer = resp.url().clone();
+        let _ = referer.set_username("");
+        let _ = referer.set_password(None);
+        referer.set_fragment(None);
+        if let Ok(value) = HeaderValue::from_str(referer.as_str()) {
+            headers.insert(REFERER, value);
+        }
+    }
+    *req.url_mut() = url;
+    Some(req)
+}
+
+fn same_origin(a: &Url, b: &Url) -> bool {
+    a.host_str() == b.host_str() && a.port_or_known_default() == b.port_or_known_default()
+}
+
+/// Collects response headers in order. Bytes of a value that are not
+/// valid UTF-8 are escaped as `\xNN` instead of failing the request.
+pub fn response_headers(resp: &Response) -> Vec<HttpHeader> {
+    resp.headers()
+        .iter()
+        .map(|(name, value)| {
+            let mut text = String::new();
+            for chunk in value.as_bytes().utf8_chunks() {
+                text.push_str(chunk.valid());
+                for byte in chunk.invalid() {
+                    text.push_str(&format!("\\x{:02X}", byte));
+                }
+            }
+            HttpHeader::new(name.as_str(), text)
+        })
+        .collect()
+}
 
+fn http_version(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_11 => "HTTP/1.1",
+        Version::HTTP_2 => "HTTP/2.0",
+        Version::HTTP_3 => "HTTP/3.0",
+        _ => "HTTP",
+    }
+}
+
+/// Sends a request and reads the response body, retrying as configured
+/// by `RequestOptions.retry`.
+pub async fn proxy_http(request: HttpRequest) -> Result<HttpResponse, HttpError> {
+    let policy = request
+        .options
+        .as_ref()
+        .and_then(|options| options.retry.as_ref());
+    with_retries(policy, &request.method, || fetch_http(&request)).await
+}
+
+async fn fetch_http(request: &HttpRequest) -> Result<HttpResponse, HttpError> {
     let start_time = Instant::now(); // Start timing
-    let response = req_builder.send().await;
+    let accepted = accepted_codings(request);
+    let resp = send_http(ClientKind::Standard, &offer_codings(request, accepted)).await?;
     let duration_ms = start_time.elapsed().as_millis(); // Calculate duration
 
-    match response {
-        Ok(resp) => {
-            let status_code = resp.status().as_u16();
-            let headers = resp
-                .headers()
-                .iter()
-                .map(|(k, v)| {
-                    Ok((
-                        k.to_string(),
-                        v.to_str()
-                            .map_err(|e| HttpError {
-                                message: format!("Header decode error: {}", e),
-                            })?
-                            .to_string(),
-                    ))
-                })
-                .collect::<Result<HashMap<String, String>, HttpError>>()?;
-            let body = resp.text().await.map_err(|e| HttpError {
-                message: format!("Failed to read response body: {}", e),
-            })?;
-            Ok(HttpResponse {
-                status_code,
-                headers,
-                body,
-                duration_ms,
-            })
+    let mut head = HttpResponse::head(&resp);
+    let max_body_bytes = request
+        .options
+        .as_ref()
+        .and_then(|options| options.max_body_bytes)
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES);
+    let download_start = Instant::now();
+    let body = read_body(resp, max_body_bytes, accepted).await?;
+    head.set_body_size(body.size, download_start.elapsed());
+    Ok(HttpResponse {
+        body: body.content,
+        body_encoding: body.encoding,
+        duration_ms,
+        ..head
+    })
+}
+
+/// Codings offered with `RequestOptions.accept_encoding`
+fn accepted_codings(request: &HttpRequest) -> &[ContentCoding] {
+    request
+        .options
+        .as_ref()
+        .and_then(|options| options.accept_encoding.as_deref())
+        .unwrap_or_default()
+}
+
+/// The request with an `Accept-Encoding` offering `accepted`, unless its
+/// headers set one. Only bodies read whole are offered compression;
+/// streamed ones are parsed as they arrive.
+fn offer_codings<'a>(request: &'a HttpRequest, accepted: &[ContentCoding]) -> Cow<'a, HttpRequest> {
+    let has_header = request
+        .headers
+        .iter()
+        .flatten()
+        .any(|header| header.name.eq_ignore_ascii_case(ACCEPT_ENCODING.as_str()));
+    if accepted.is_empty() || has_header {
+        return Cow::Borrowed(request);
+    }
+
+    let value = accepted
+        .iter()
+        .map(|coding| coding.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut request = request.clone();
+    request
+        .headers
+        .get_or_insert_with(Vec::new)
+        .push(HttpHeader::new(ACCEPT_ENCODING.as_str(), value));
+    Cow::Owned(request)
+}
+
+/// Sends a request and streams the response body to `path` rather than
+/// reading it into memory. The returned `body` is the path. Only
+/// connecting is time-limited so large downloads can complete.
+pub async fn save_http(request: HttpRequest, path: PathBuf) -> Result<HttpResponse, HttpError> {
+    let start_time = Instant::now();
+    let accepted = accepted_codings(&request);
+    let resp = send_http(ClientKind::Streaming, &offer_codings(&request, accepted)).await?;
+    let mut head = HttpResponse::head(&resp);
+    let download_start = Instant::now();
+    let size = save_body(resp, &path, accepted).await?;
+    head.set_body_size(size, download_start.elapsed());
+    Ok(HttpResponse {
+        body: path.to_string_lossy().into_owned(),
+        body_encoding: BodyEncoding::File,
+        duration_ms: start_time.elapsed().as_millis(),
+        ..head
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::digest_auth::DigestCredentials;
+    use crate::common::secret_vault;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers `POST /login` with a redirect to `/home` that sets a session
+    /// cookie, `/home` with the cookies it received and any other path
+    /// with a redirect to itself.
+    async fn login_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 4096];
+                let read = stream.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..read]).into_owned();
+                let cookies = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("cookie: "))
+                    .unwrap_or("none")
+                    .to_string();
+                let response = if request.starts_with("POST /login ") {
+                    reply(
+                        "302 Found\r\nLocation: /home\r\nSet-Cookie: session=abc; Path=/",
+                        "",
+                    )
+                } else if request.starts_with("GET /home ") {
+                    reply("200 OK", &cookies)
+                } else {
+                    reply("302 Found\r\nLocation: /loop", "")
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    fn reply(head: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            head,
+            body.len(),
+            body
+        )
+    }
+
+    async fn cookie_jar() -> CookieJar {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        sqlx::query(
+            "INSERT INTO endpoint (id, name, endpoint_type, url, status)
+             VALUES ('e1', 'Test', 'graphql', 'http://localhost', 'active')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        secret_vault::load(&pool).await.unwrap();
+        CookieJar::new(pool, "e1")
+    }
+
+    fn request(url: String, cookie_jar: CookieJar, options: Option<RequestOptions>) -> HttpRequest {
+        HttpRequest {
+            method: "POST".to_string(),
+            url,
+            headers: None,
+            body: Some(HttpBody::Json("{}".to_string())),
+            options,
+            endpoint_id: None,
+            signing: None,
+            cookie_jar: Some(cookie_jar),
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_cookies_set_by_redirects() {
+        let url = login_server().await;
+        let response = proxy_http(request(format!("{}/login", url), cookie_jar().await, None))
+            .await
+            .unwrap_or_else(|e| panic!("{}", e.message));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, "session=abc");
+        // The body was written on the hop that was redirected.
+        let timings = response.timings.unwrap();
+        assert!(timings.request_write_ms.is_some());
+        assert_eq!(timings.tls_ms, None);
+    }
+
+    #[tokio::test]
+    async fn limits_redirects_followed_for_the_jar() {
+        let url = login_server().await;
+        let options = RequestOptions {
+            max_redirects: Some(2),
+            ..Default::default()
+        };
+        let error = proxy_http(request(
+            format!("{}/loop", url),
+            cookie_jar().await,
+            Some(options),
+        ))
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(error.message, "Too many redirects, stopped after 2");
+
+        let options = RequestOptions {
+            follow_redirects: Some(false),
+            ..Default::default()
+        };
+        let response = proxy_http(request(
+            format!("{}/loop", url),
+            cookie_jar().await,
+            Some(options),
+        ))
+        .await
+        .unwrap_or_else(|e| panic!("{}", e.message));
+        assert_eq!(response.status_code, 302);
+    }
+
+    /// Answers requests with a Digest challenge unless they carry an
+    /// `Authorization` header, after reading their whole body.
+    async fn digest_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/upload", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                let text = loop {
+                    let read = stream.read(&mut buffer).await.unwrap_or(0);
+                    request.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&request).into_owned();
+                    let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length: "))
+                        .map_or(0, |length| length.parse().unwrap());
+                    if read == 0 || body.len() >= length {
+                        break text;
+                    }
+                };
+                let response = if text.contains("authorization: Digest ") {
+                    reply("200 OK", "welcome")
+                } else {
+                    reply(
+                        "401 Unauthorized\r\nWWW-Authenticate: Digest realm=\"r\", qop=\"auth\", nonce=\"n\"",
+                        "",
+                    )
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn answers_digest_challenges_only_with_bodies_that_can_be_sent_again() {
+        let url = digest_server().await;
+        let path = std::env::temp_dir().join(format!("digest-{}.txt", uuid::Uuid::new_v4()));
+        tokio::fs::write(&path, "file body").await.unwrap();
+        let request = HttpRequest {
+            method: "PUT".to_string(),
+            url,
+            headers: None,
+            body: Some(HttpBody::File(path.to_string_lossy().into_owned())),
+            options: None,
+            endpoint_id: None,
+            signing: Some(RequestSigning::Digest(DigestCredentials {
+                username: "file-uploader".to_string(),
+                password: "secret".to_string(),
+            })),
+            cookie_jar: None,
+        };
+
+        let error = proxy_http(request.clone()).await.err().unwrap();
+        assert!(error.message.starts_with("Digest authentication needs"));
+        // The challenge was kept, so the next request answers it up front.
+        let response = proxy_http(request.clone())
+            .await
+            .unwrap_or_else(|e| panic!("{}", e.message));
+        assert_eq!(response.body, "welcome");
+
+        let mut request = request;
+        request.body = Some(HttpBody::Text("text body".to_string()));
+        if let Some(RequestSigning::Digest(credentials)) = &mut request.signing {
+            credentials.username = "text-uploader".to_string();
         }
-        Err(err) => Err(HttpError {
-            message: format!("HTTP request failed: {}", err),
-        }),
+        let response = proxy_http(request)
+            .await
+            .unwrap_or_else(|e| panic!("{}", e.message));
+        assert_eq!(response.body, "welcome");
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sends_to_unix_sockets_only_when_asked() {
+        let mut request = HttpRequest {
+            method: "GET".to_string(),
+            url: "unix:///var/run/docker.sock:/v1.43/info".to_string(),
+            headers: None,
+            body: None,
+            options: None,
+            endpoint_id: None,
+            signing: None,
+            cookie_jar: None,
+        };
+        assert!(proxy_http(request.clone()).await.is_err());
+
+        use_unix_socket(&mut request);
+        assert_eq!(request.url, "http://localhost/v1.43/info");
+        assert_eq!(
+            request.options.unwrap().unix_socket.as_deref(),
+            Some("/var/run/docker.sock")
+        );
     }
 }
@@ -0,0 +1,154 @@
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::multipart::{Form, Part};
+use reqwest::RequestBuilder;
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use typeshare::typeshare;
+
+use crate::common::proxy_http::HttpError;
+
+/// Body of a request. Each kind implies a `Content-Type`, which is sent
+/// unless the request's headers set one; multipart bodies always get
+/// theirs since it carries the boundary.
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", content = "content", rename_all = "camelCase")]
+pub enum HttpBody {
+    /// Text sent as is, `text/plain`
+    Text(String),
+    /// JSON text sent as is, `application/json`
+    Json(String),
+    /// Fields encoded as `application/x-www-form-urlencoded`
+    UrlEncoded(Vec<FormField>),
+    /// Fields of a `multipart/form-data` body
+    Multipart(Vec<MultipartPart>),
+    /// Path of a local file whose bytes are streamed as the body,
+    /// `application/octet-stream`
+    File(String),
+}
+
+/// A field of an `application/x-www-form-urlencoded` body
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FormField {
+    pub name: String,
+    pub value: String,
+}
+
+impl HttpBody {
+    /// Sets the body of a request being built. Files are opened here but
+    /// only read while the request is sent.
+    pub async fn apply(&self, builder: RequestBuilder) -> Result<RequestBuilder, HttpError> {
+        Ok(match self {
+            HttpBody::Text(text) | HttpBody::Json(text) => builder.body(text.clone()),
+            HttpBody::UrlEncoded(fields) => builder.form(
+                &fields
+                    .iter()
+                    .map(|field| (field.name.as_str(), field.value.as_str()))
+                    .collect::<Vec<_>>(),
+            ),
+            HttpBody::Multipart(parts) => builder.multipart(multipart_form(parts).await?),
+            HttpBody::File(path) => {
+                let file = File::open(path)
+                    .await
+                    .map_err(|e| HttpError::new(format!("Failed to open {}: {}", path, e)))?;
+                let length = file
+                    .metadata()
+                    .await
+                    .map_err(|e| HttpError::new(format!("Failed to read {}: {}", path, e)))?
+                    .len();
+                builder.header(CONTENT_LENGTH, length).body(file)
+            }
+        })
+    }
+
+    /// The `Content-Type` implied by the body, sent when the request's
+    /// headers set none. Multipart bodies set theirs in `apply`.
+    pub fn content_type(&self) -> Option<&'static str> {
+        match self {
+            HttpBody::Text(_) => Some("text/plain; charset=utf-8"),
+            HttpBody::Json(_) => Some("application/json"),
+            HttpBody::UrlEncoded(_) => Some("application/x-www-form-urlencoded"),
+            HttpBody::Multipart(_) => None,
+            HttpBody::File(_) => Some("application/octet-stream"),
+        }
+    }
+
+    /// The body as text, for kinds that hold it in memory.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            HttpBody::Text(text) | HttpBody::Json(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// A field of a `multipart/form-data` body
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MultipartPart {
+    pub name: String,
+    pub source: PartSource,
+    /// File name of the part; the name of the file for file parts when unset
+    pub filename: Option<String>,
+    /// Content type of the part; guessed from the file extension for file
+    /// parts when unset
+    pub content_type: Option<String>,
+}
+
+/// Where the content of a multipart field comes from
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", content = "content", rename_all = "camelCase")]
+pub enum PartSource {
+    Text(String),
+    /// Path of a local file, streamed from disk when the request is sent
+    File(String),
+}
+
+impl MultipartPart {
+    pub fn file(name: impl Into<String>, path: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            source: PartSource::File(path.into()),
+            filename: None,
+            content_type: None,
+        }
+    }
+
+    pub fn text(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            source: PartSource::Text(value.into()),
+            filename: None,
+            content_type: None,
+        }
+    }
+}
+
+/// Builds a `multipart/form-data` body. Files are opened here but only
+/// read while the request is sent.
+async fn multipart_form(parts: &[MultipartPart]) -> Result<Form, HttpError> {
+    let mut form = Form::new();
+    for part in parts {
+        let mut field = match &part.source {
+            PartSource::Text(value) => Part::text(value.clone()),
+            PartSource::File(path) => Part::file(path)
+                .await
+                .map_err(|e| HttpError::new(format!("Failed to open {}: {}", path, e)))?,
+        };
+        if let Some(filename) = &part.filename {
+            field = field.file_name(filename.clone());
+        }
+        if let Some(content_type) = &part.content_type {
+            field = field.mime_str(content_type).map_err(|e| {
+                HttpError::new(format!(
+                    "Invalid content type {} for {}: {}",
+                    content_type, part.name, e
+                ))
+            })?;
+        }
+        form = form.part(part.name.clone(), field);
+    }
+    Ok(form)
+}
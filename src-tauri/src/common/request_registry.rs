@@ -0,0 +1,62 @@
+use futures_util::future::{AbortHandle, Abortable};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::common::proxy_http::HttpError;
+
+/// In-flight requests that can be cancelled, keyed by request id. Stored in
+/// Tauri state.
+#[derive(Default)]
+pub struct RequestRegistry {
+    /// Abort handle of each request, tagged so that a finished request does
+    /// not unregister a newer one that reused its id
+    requests: Mutex<HashMap<String, (u64, AbortHandle)>>,
+    next_tag: AtomicU64,
+}
+
+impl RequestRegistry {
+    /// Runs `request`, registering it under `request_id` so `cancel` can
+    /// abort it. Requests without an id cannot be cancelled.
+    pub async fn run<T, F>(&self, request_id: Option<String>, request: F) -> Result<T, HttpError>
+    where
+        F: Future<Output = Result<T, HttpError>>,
+    {
+        let Some(request_id) = request_id else {
+            return request.await;
+        };
+
+        let tag = self.next_tag.fetch_add(1, Ordering::Relaxed);
+        let (handle, registration) = AbortHandle::new_pair();
+        // A request reusing the id of a running one replaces it.
+        if let Some((_, previous)) = self.lock().insert(request_id.clone(), (tag, handle)) {
+            previous.abort();
+        }
+
+        let result = Abortable::new(request, registration).await;
+
+        let mut requests = self.lock();
+        if requests.get(&request_id).is_some_and(|(t, _)| *t == tag) {
+            requests.remove(&request_id);
+        }
+        drop(requests);
+
+        result.unwrap_or_else(|_| Err(HttpError::cancelled()))
+    }
+
+    /// Aborts a request. Returns `false` if it is not in flight.
+    pub fn cancel(&self, request_id: &str) -> bool {
+        match self.lock().remove(request_id) {
+            Some((_, handle)) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, (u64, AbortHandle)>> {
+        self.requests.lock().expect("request registry poisoned")
+    }
+}
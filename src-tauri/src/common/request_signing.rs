@@ -0,0 +1,574 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use hmac::{digest::KeyInit, Hmac, Mac};
+use log::debug;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, HOST};
+use reqwest::{Request, Response, Url};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::BTreeMap;
+use typeshare::typeshare;
+
+use crate::common::digest_auth::DigestCredentials;
+use crate::database::entities::endpoint_entity::{AuthConfig, AuthType};
+
+const AWS_ALGORITHM: &str = "AWS4-HMAC-SHA256";
+/// Headers left out of SigV4 signatures since proxies or the HTTP stack
+/// may change them
+const AWS_UNSIGNED_HEADERS: [&str; 5] = [
+    "authorization",
+    "connection",
+    "expect",
+    "user-agent",
+    "x-amzn-trace-id",
+];
+/// Headers added by `Hmac` auth when `hmac_header_template` is not set
+const DEFAULT_HMAC_TEMPLATE: &str = "X-Signature: {signature}\nX-Timestamp: {timestamp}";
+
+/// Hash function of an HMAC signature
+#[typeshare]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HmacAlgorithm {
+    Sha1,
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+/// How a request is signed right before it is sent, so the signature
+/// covers exactly the method, URL, headers and body that go out
+#[derive(Debug, Clone)]
+pub enum RequestSigning {
+    AwsSigV4(AwsSigV4),
+    Hmac(HmacSignature),
+    /// Answers HTTP Digest challenges, see `retry_challenge`
+    Digest(DigestCredentials),
+}
+
+/// AWS Signature Version 4 credentials and scope
+#[derive(Debug, Clone)]
+pub struct AwsSigV4 {
+    pub region: String,
+    pub service: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+/// Shared-secret signature of `METHOD\nPATH?QUERY\nTIMESTAMP\nSHA256(BODY)`
+/// sent in the headers described by `header_template`
+#[derive(Debug, Clone)]
+pub struct HmacSignature {
+    pub algorithm: HmacAlgorithm,
+    pub secret: String,
+    pub key_id: String,
+    pub header_template: String,
+}
+
+impl RequestSigning {
+    /// The signing configured by an endpoint's authentication, if any.
+    pub fn from_auth(auth: &AuthConfig) -> Result<Option<Self>, String> {
+        match auth.auth_type {
+            AuthType::AwsSigV4 => Ok(Some(Self::AwsSigV4(AwsSigV4 {
+                region: required(&auth.aws_region, "AWS region")?,
+                service: required(&auth.aws_service, "AWS service")?,
+                access_key_id: required(&auth.aws_access_key_id, "AWS access key id")?,
+                secret_access_key: required(&auth.aws_secret_access_key, "AWS secret access key")?,
+                session_token: auth
+                    .aws_session_token
+                    .clone()
+                    .filter(|token| !token.is_empty()),
+            }))),
+            AuthType::Hmac => Ok(Some(Self::Hmac(HmacSignature {
+                algorithm: auth.hmac_algorithm.unwrap_or_default(),
+                secret: required(&auth.hmac_secret, "HMAC secret")?,
+                key_id: auth.hmac_key_id.clone().unwrap_or_default(),
+                header_template: auth
+                    .hmac_header_template
+                    .clone()
+                    .filter(|template| !template.trim().is_empty())
+                    .unwrap_or_else(|| DEFAULT_HMAC_TEMPLATE.to_string()),
+            }))),
+            AuthType::Digest => Ok(Some(Self::Digest(DigestCredentials {
+                username: required(&auth.username, "Username")?,
+                password: auth.password.clone().unwrap_or_default(),
+            }))),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn sign(&self, request: &mut Request) -> Result<(), String> {
+        match self {
+            Self::AwsSigV4(credentials) => credentials.sign(request, Utc::now()),
+            Self::Hmac(signature) => signature.sign(request, Utc::now()),
+            Self::Digest(credentials) => credentials.authorize(request),
+        }
+    }
+
+    /// Whether the request to `url` should be signed and sent again after
+    /// `response`, which is the case when it carries a new challenge.
+    pub fn retry_challenge(&self, url: &Url, response: &Response) -> bool {
+        match self {
+            Self::Digest(credentials) => credentials.accept_challenge(url, response),
+            _ => false,
+        }
+    }
+}
+
+fn required(value: &Option<String>, name: &str) -> Result<String, String> {
+    value
+        .clone()
+        .filter(|value| !value.trim().is_empty())
+        .ok_or_else(|| format!("{} is required to sign requests", name))
+}
+
+impl AwsSigV4 {
+    /// Adds `X-Amz-Date`, the session token if any and the `Authorization`
+    /// header. All headers present at this point are signed, except those
+    /// in `AWS_UNSIGNED_HEADERS`.
+    pub fn sign(&self, request: &mut Request, now: DateTime<Utc>) -> Result<(), String> {
+        // Services other than S3 expect normalized paths with each segment
+        // encoded twice.
+        let parts = self.sign_with(request, now, self.service != "s3")?;
+        // Logged so that signature mismatches reported by AWS can be compared.
+        debug!(
+            "SigV4 signature {} of canonical request:\n{}\nstring to sign:\n{}",
+            parts.signature, parts.canonical_request, parts.string_to_sign
+        );
+        Ok(())
+    }
+
+    fn sign_with(
+        &self,
+        request: &mut Request,
+        now: DateTime<Utc>,
+        normalize_path: bool,
+    ) -> Result<SigV4Parts, String> {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &amz_date[..8];
+        let payload_hash = hex(&Sha256::digest(body_bytes(request)?));
+
+        let headers = request.headers_mut();
+        insert_header(headers, "x-amz-date", &amz_date)?;
+        if let Some(token) = &self.session_token {
+            insert_header(headers, "x-amz-security-token", token)?;
+        }
+        // S3 requires the payload hash as a header as well.
+        if self.service == "s3" {
+            insert_header(headers, "x-amz-content-sha256", &payload_hash)?;
+        }
+
+        let mut canonical_headers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        if !request.headers().contains_key(HOST) {
+            canonical_headers.insert("host".to_string(), vec![host_of(request.url())?]);
+        }
+        for (name, value) in request.headers() {
+            if AWS_UNSIGNED_HEADERS.contains(&name.as_str()) {
+                continue;
+            }
+            let value = value
+                .to_str()
+                .map_err(|_| format!("Header {} cannot be signed, it is not ASCII", name))?;
+            canonical_headers
+                .entry(name.as_str().to_string())
+                .or_default()
+                .push(value.split_whitespace().collect::<Vec<_>>().join(" "));
+        }
+        let signed_headers = canonical_headers
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = canonical_headers
+            .iter()
+            .map(|(name, values)| format!("{}:{}\n", name, values.join(",")))
+            .collect();
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            request.method().as_str(),
+            canonical_uri(request.url(), normalize_path),
+            canonical_query(request.url()),
+            canonical_headers,
+            signed_headers,
+            payload_hash
+        );
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}",
+            AWS_ALGORITHM,
+            amz_date,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut key = mac::<Hmac<Sha256>>(
+            format!("AWS4{}", self.secret_access_key).as_bytes(),
+            date.as_bytes(),
+        );
+        for part in [self.region.as_str(), self.service.as_str(), "aws4_request"] {
+            key = mac::<Hmac<Sha256>>(&key, part.as_bytes());
+        }
+        let signature = hex(&mac::<Hmac<Sha256>>(&key, string_to_sign.as_bytes()));
+
+        insert_header(
+            request.headers_mut(),
+            AUTHORIZATION.as_str(),
+            &format!(
+                "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+                AWS_ALGORITHM, self.access_key_id, scope, signed_headers, signature
+            ),
+        )?;
+        Ok(SigV4Parts {
+            canonical_request,
+            string_to_sign,
+            signature,
+        })
+    }
+}
+
+/// Intermediate values of a SigV4 signature
+struct SigV4Parts {
+    canonical_request: String,
+    string_to_sign: String,
+    signature: String,
+}
+
+impl HmacSignature {
+    /// Signs the request and adds the headers of the template, one
+    /// `Name: value` per line, with `{signature}` (hex),
+    /// `{signature_base64}`, `{timestamp}` (Unix seconds) and `{key_id}`
+    /// replaced.
+    pub fn sign(&self, request: &mut Request, now: DateTime<Utc>) -> Result<(), String> {
+        let timestamp = now.timestamp().to_string();
+        let url = request.url();
+        let target = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}",
+            request.method().as_str(),
+            target,
+            timestamp,
+            hex(&Sha256::digest(body_bytes(request)?))
+        );
+        let key = self.secret.as_bytes();
+        let data = string_to_sign.as_bytes();
+        let signature = match self.algorithm {
+            HmacAlgorithm::Sha1 => mac::<Hmac<Sha1>>(key, data),
+            HmacAlgorithm::Sha256 => mac::<Hmac<Sha256>>(key, data),
+            HmacAlgorithm::Sha512 => mac::<Hmac<Sha512>>(key, data),
+        };
+
+        for line in self
+            .header_template
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+        {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid HMAC header template line: {}", line))?;
+            let value = value
+                .trim()
+                .replace("{signature}", &hex(&signature))
+                .replace("{signature_base64}", &STANDARD.encode(&signature))
+                .replace("{timestamp}", &timestamp)
+                .replace("{key_id}", &self.key_id);
+            insert_header(request.headers_mut(), name.trim(), &value)?;
+        }
+        Ok(())
+    }
+}
+
+fn mac<M: Mac + KeyInit>(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <M as KeyInit>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn body_bytes(request: &Request) -> Result<&[u8], String> {
+    match request.body() {
+        Some(body) => body
+            .as_bytes()
+            .ok_or_else(|| "Streaming request bodies cannot be signed".to_string()),
+        None => Ok(&[]),
+    }
+}
+
+fn insert_header(headers: &mut HeaderMap, name: &str, value: &str) -> Result<(), String> {
+    let name = HeaderName::from_bytes(name.as_bytes())
+        .map_err(|e| format!("Invalid header name {}: {}", name, e))?;
+    let value =
+        HeaderValue::from_str(value).map_err(|e| format!("Invalid value for {}: {}", name, e))?;
+    headers.insert(name, value);
+    Ok(())
+}
+
+/// The `Host` header the HTTP stack will send for `url`.
+fn host_of(url: &Url) -> Result<String, String> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("URL has no host: {}", url))?;
+    Ok(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
+}
+
+/// With `normalize`, empty segments are dropped and each segment is
+/// encoded twice; `Url` already resolves `.` and `..` segments.
+fn canonical_uri(url: &Url, normalize: bool) -> String {
+    let path = match url.path() {
+        "" => "/",
+        path => path,
+    };
+    let segments: Vec<&str> = path.split('/').collect();
+    let last = segments.len() - 1;
+    segments
+        .iter()
+        .enumerate()
+        .filter(|(i, segment)| !normalize || !segment.is_empty() || *i == 0 || *i == last)
+        .map(|(_, segment)| {
+            let encoded = uri_encode(&percent_decode(segment));
+            if normalize {
+                uri_encode(encoded.as_bytes())
+            } else {
+                encoded
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Query strings are form-urlencoded, so a `+` stands for a space and is
+/// signed as `%20`, while a literal plus sign arrives as `%2B`.
+fn canonical_query(url: &Url) -> String {
+    let mut pairs: Vec<(String, String)> = url
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let pair = pair.replace('+', " ");
+            let (name, value) = pair.split_once('=').unwrap_or((&pair, ""));
+            (
+                uri_encode(&percent_decode(name)),
+                uri_encode(&percent_decode(value)),
+            )
+        })
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Percent-encodes everything but the unreserved characters of RFC 3986.
+fn uri_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use reqwest::{Body, Method};
+
+    const EMPTY_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    /// Signs a request with the credentials of the aws-sig-v4-test-suite.
+    fn suite_sign(
+        method: Method,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&'static str>,
+        normalize_path: bool,
+    ) -> (Request, SigV4Parts) {
+        let credentials = AwsSigV4 {
+            region: "us-east-1".to_string(),
+            service: "service".to_string(),
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+        let mut request = Request::new(method, Url::parse(url).unwrap());
+        for (name, value) in headers {
+            insert_header(request.headers_mut(), name, value).unwrap();
+        }
+        *request.body_mut() = body.map(Body::from);
+        let now = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+        let parts = credentials
+            .sign_with(&mut request, now, normalize_path)
+            .unwrap();
+        (request, parts)
+    }
+
+    fn assert_suite(parts: &SigV4Parts, canonical_request: &str, signature: &str) {
+        assert_eq!(parts.canonical_request, canonical_request);
+        assert_eq!(
+            parts.string_to_sign,
+            format!(
+                "AWS4-HMAC-SHA256\n20150830T123600Z\n20150830/us-east-1/service/aws4_request\n{}",
+                hex(&Sha256::digest(canonical_request.as_bytes()))
+            )
+        );
+        assert_eq!(parts.signature, signature);
+    }
+
+    fn get_canonical_request(path: &str, query: &str) -> String {
+        format!(
+            "GET\n{}\n{}\nhost:example.amazonaws.com\nx-amz-date:20150830T123600Z\n\nhost;x-amz-date\n{}",
+            path, query, EMPTY_HASH
+        )
+    }
+
+    #[test]
+    fn signs_get_vanilla() {
+        let (request, parts) = suite_sign(
+            Method::GET,
+            "https://example.amazonaws.com/",
+            &[],
+            None,
+            true,
+        );
+        assert_suite(
+            &parts,
+            &get_canonical_request("/", ""),
+            "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31",
+        );
+        assert_eq!(
+            request.headers()[AUTHORIZATION],
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn signs_get_vanilla_query_order_key() {
+        let (_, parts) = suite_sign(
+            Method::GET,
+            "https://example.amazonaws.com/?Param1=value2&Param1=Value1",
+            &[],
+            None,
+            true,
+        );
+        assert_suite(
+            &parts,
+            &get_canonical_request("/", "Param1=Value1&Param1=value2"),
+            "eedbc4e291e521cf13422ffca22be7d2eb8146eecf653089df300a15b2382bd1",
+        );
+    }
+
+    #[test]
+    fn signs_get_space() {
+        // The suite encodes paths once, as done for S3.
+        let (_, parts) = suite_sign(
+            Method::GET,
+            "https://example.amazonaws.com/example space/",
+            &[],
+            None,
+            false,
+        );
+        assert_suite(
+            &parts,
+            &get_canonical_request("/example%20space/", ""),
+            "652487583200325589f1fba4c7e578f72c47cb61beeca81406b39ddec1366741",
+        );
+    }
+
+    #[test]
+    fn signs_post_x_www_form_urlencoded() {
+        let (_, parts) = suite_sign(
+            Method::POST,
+            "https://example.amazonaws.com/",
+            &[("content-type", "application/x-www-form-urlencoded")],
+            Some("Param1=value1"),
+            true,
+        );
+        assert_suite(
+            &parts,
+            "POST\n/\n\ncontent-type:application/x-www-form-urlencoded\n\
+             host:example.amazonaws.com\nx-amz-date:20150830T123600Z\n\n\
+             content-type;host;x-amz-date\n\
+             9095672bbd1f56dfc5b65f3e153adc8731a4a654192329106275f4c7b24d0b6e",
+            "ff11897932ad3f4e8b18135d722051e5ac45fc38421b1da7b9d196a0fe09473a",
+        );
+    }
+
+    #[test]
+    fn signs_normalize_path() {
+        let (_, parts) = suite_sign(
+            Method::GET,
+            "https://example.amazonaws.com//example//",
+            &[],
+            None,
+            true,
+        );
+        assert_suite(
+            &parts,
+            &get_canonical_request("/example/", ""),
+            "9a624bd73a37c9a373b5312afbebe7a714a789de108f0bdfe846570885f57e84",
+        );
+
+        let (_, parts) = suite_sign(
+            Method::GET,
+            "https://example.amazonaws.com/example1/example2/../..",
+            &[],
+            None,
+            true,
+        );
+        assert_suite(
+            &parts,
+            &get_canonical_request("/", ""),
+            "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31",
+        );
+    }
+
+    #[test]
+    fn signs_plus_in_queries_as_space() {
+        let url = Url::parse("https://example.amazonaws.com/?q=a+b&sum=1%2B1").unwrap();
+        assert_eq!(canonical_query(&url), "q=a%20b&sum=1%2B1");
+    }
+}
@@ -0,0 +1,352 @@
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, ZlibDecoder, ZstdDecoder};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use encoding_rs::{Encoding, UTF_8};
+use futures_util::TryStreamExt;
+use log::{debug, warn};
+use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio_util::io::StreamReader;
+use typeshare::typeshare;
+
+use crate::common::proxy_http::HttpError;
+
+/// Largest body kept in memory when `max_body_bytes` is not set
+pub const DEFAULT_MAX_BODY_BYTES: u32 = 10 * 1024 * 1024;
+
+/// Directory under the system temp directory holding bodies too large to
+/// keep in memory; emptied on startup.
+const TEMP_DIR: &str = "query-box-responses";
+
+/// How `HttpResponse.body` holds the response body
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+#[typeshare]
+pub enum BodyEncoding {
+    /// The body decoded as text, using the charset of its content type
+    #[default]
+    Text,
+    /// The raw bytes of a binary body, base64-encoded
+    Base64,
+    /// The path of a file holding the bytes of a body that exceeded
+    /// `max_body_bytes`, or of a body saved with `save_response_to_file`
+    File,
+}
+
+/// A compression of response bodies, named as in `Accept-Encoding`
+#[typeshare]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentCoding {
+    Gzip,
+    Deflate,
+    Br,
+    Zstd,
+}
+
+impl ContentCoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Deflate => "deflate",
+            ContentCoding::Br => "br",
+            ContentCoding::Zstd => "zstd",
+        }
+    }
+
+    /// The compression of a response among `accepted`, or `None` when it
+    /// is not compressed or uses another or several codings, which are
+    /// then left as received.
+    fn of(resp: &Response, accepted: &[ContentCoding]) -> Option<Self> {
+        let coding = resp.headers().get(CONTENT_ENCODING)?.to_str().ok()?.trim();
+        accepted
+            .iter()
+            .copied()
+            .find(|accepted| accepted.as_str().eq_ignore_ascii_case(coding))
+    }
+}
+
+/// Sizes of a body read by `read_body` or `save_body`
+#[derive(Debug, Clone, Copy)]
+pub struct BodySize {
+    /// Bytes received
+    pub received: u64,
+    /// Bytes after decompression, the same as `received` when the body was
+    /// not decompressed
+    pub decoded: u64,
+}
+
+/// A response body read by `read_body`
+pub struct ResponseBody {
+    pub content: String,
+    pub encoding: BodyEncoding,
+    pub size: BodySize,
+}
+
+/// Reads a response body, keeping at most `max_bytes` in memory. Bodies
+/// compressed with one of the `accepted` codings are decompressed first.
+/// Textual content types are decoded, other content is base64-encoded, and
+/// larger bodies are written to a temporary file instead.
+pub async fn read_body(
+    resp: Response,
+    max_bytes: u32,
+    accepted: &[ContentCoding],
+) -> Result<ResponseBody, HttpError> {
+    let coding = ContentCoding::of(&resp, accepted);
+    let content = ContentType::of(&resp, coding.is_some());
+    let max_bytes = usize::try_from(max_bytes).unwrap_or(usize::MAX);
+    let mut reader = BodyReader::new(resp, coding);
+
+    let mut bytes = Vec::new();
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        let chunk = &chunk[..read];
+        if bytes.len() + chunk.len() <= max_bytes {
+            bytes.extend_from_slice(chunk);
+            continue;
+        }
+
+        let path = temp_file_path()?;
+        debug!(
+            "Response body exceeds {} bytes, writing it to {}",
+            max_bytes,
+            path.display()
+        );
+        let mut file = create_file(&path).await?;
+        write_all(&mut file, &path, &bytes).await?;
+        write_all(&mut file, &path, chunk).await?;
+        let rest = copy_body(&mut reader, &mut file, &path).await?;
+        return Ok(ResponseBody {
+            content: path.to_string_lossy().into_owned(),
+            encoding: BodyEncoding::File,
+            size: reader.size((bytes.len() + chunk.len()) as u64 + rest),
+        });
+    }
+
+    let (text, encoding) = if content.is_textual(&bytes) {
+        (content.decode(&bytes), BodyEncoding::Text)
+    } else {
+        (STANDARD.encode(&bytes), BodyEncoding::Base64)
+    };
+    Ok(ResponseBody {
+        content: text,
+        encoding,
+        size: reader.size(bytes.len() as u64),
+    })
+}
+
+/// Streams a response body to `path`, replacing the file. Bodies
+/// compressed with one of the `accepted` codings are decompressed.
+pub async fn save_body(
+    resp: Response,
+    path: &Path,
+    accepted: &[ContentCoding],
+) -> Result<BodySize, HttpError> {
+    let coding = ContentCoding::of(&resp, accepted);
+    let mut reader = BodyReader::new(resp, coding);
+    let mut file = create_file(path).await?;
+    let written = copy_body(&mut reader, &mut file, path).await?;
+    Ok(reader.size(written))
+}
+
+/// Bytes read from a body at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reads a response body as it arrives, decompressing it if needed, and
+/// counts the bytes received.
+struct BodyReader {
+    reader: Pin<Box<dyn AsyncRead + Send>>,
+    received: Arc<AtomicU64>,
+}
+
+impl BodyReader {
+    fn new(resp: Response, coding: Option<ContentCoding>) -> Self {
+        let received = Arc::new(AtomicU64::new(0));
+        let counter = received.clone();
+        let stream = resp
+            .bytes_stream()
+            .inspect_ok(move |chunk| {
+                counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            })
+            .map_err(std::io::Error::other);
+        let raw = StreamReader::new(stream);
+        let reader: Pin<Box<dyn AsyncRead + Send>> = match coding {
+            Some(ContentCoding::Gzip) => Box::pin(GzipDecoder::new(raw)),
+            Some(ContentCoding::Deflate) => Box::pin(ZlibDecoder::new(raw)),
+            Some(ContentCoding::Br) => Box::pin(BrotliDecoder::new(raw)),
+            Some(ContentCoding::Zstd) => Box::pin(ZstdDecoder::new(raw)),
+            None => Box::pin(raw),
+        };
+        Self { reader, received }
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, HttpError> {
+        self.reader.read(buf).await.map_err(read_error)
+    }
+
+    /// The sizes of the body once `decoded` bytes have been read.
+    fn size(&self, decoded: u64) -> BodySize {
+        BodySize {
+            received: self.received.load(Ordering::Relaxed),
+            decoded,
+        }
+    }
+}
+
+/// Removes the bodies written by earlier sessions.
+pub fn clear_temp_files() {
+    let dir = std::env::temp_dir().join(TEMP_DIR);
+    match std::fs::remove_dir_all(&dir) {
+        Ok(()) => debug!("Removed response bodies in {}", dir.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Failed to remove {}: {}", dir.display(), e),
+    }
+}
+
+fn temp_file_path() -> Result<PathBuf, HttpError> {
+    let dir = std::env::temp_dir().join(TEMP_DIR);
+    std::fs::create_dir_all(&dir).map_err(|e| {
+        HttpError::new(format!(
+            "Failed to create directory {}: {}",
+            dir.display(),
+            e
+        ))
+    })?;
+    Ok(dir.join(format!("response-{}", uuid::Uuid::new_v4())))
+}
+
+async fn create_file(path: &Path) -> Result<File, HttpError> {
+    File::create(path)
+        .await
+        .map_err(|e| HttpError::new(format!("Failed to create {}: {}", path.display(), e)))
+}
+
+async fn write_all(file: &mut File, path: &Path, bytes: &[u8]) -> Result<(), HttpError> {
+    file.write_all(bytes)
+        .await
+        .map_err(|e| HttpError::new(format!("Failed to write {}: {}", path.display(), e)))
+}
+
+async fn copy_body(
+    reader: &mut BodyReader,
+    file: &mut File,
+    path: &Path,
+) -> Result<u64, HttpError> {
+    let mut written = 0;
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        write_all(file, path, &chunk[..read]).await?;
+        written += read as u64;
+    }
+    file.flush()
+        .await
+        .map_err(|e| HttpError::new(format!("Failed to write {}: {}", path.display(), e)))?;
+    Ok(written)
+}
+
+fn read_error(e: std::io::Error) -> HttpError {
+    // Decoders report a corrupt compressed body as invalid data.
+    if e.kind() == std::io::ErrorKind::InvalidData {
+        return HttpError::new(format!("Failed to decompress response body: {}", e));
+    }
+    HttpError::transient(format!("Failed to read response body: {}", e))
+}
+
+/// What the response headers say about the body
+struct ContentType {
+    /// Media type without parameters, lowercase
+    mime: Option<String>,
+    charset: Option<String>,
+    /// The body is still compressed, e.g. `gzip`
+    encoded: bool,
+}
+
+impl ContentType {
+    fn of(resp: &Response, decompressed: bool) -> Self {
+        let header = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+        let mut params = header(CONTENT_TYPE).unwrap_or_default().split(';');
+        let mime = params
+            .next()
+            .map(|mime| mime.trim().to_ascii_lowercase())
+            .filter(|mime| !mime.is_empty());
+        let charset = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+            .map(|(_, charset)| charset.trim().trim_matches('"').to_string());
+        let encoded = !decompressed
+            && header(CONTENT_ENCODING)
+                .is_some_and(|coding| !coding.eq_ignore_ascii_case("identity"));
+        Self {
+            mime,
+            charset,
+            encoded,
+        }
+    }
+
+    /// Whether the body is text. Without a content type, bodies that are
+    /// valid UTF-8 are taken as text.
+    fn is_textual(&self, body: &[u8]) -> bool {
+        if self.encoded {
+            return false;
+        }
+        let Some(mime) = &self.mime else {
+            return std::str::from_utf8(body).is_ok_and(|text| !text.contains('\0'));
+        };
+        if self.charset.is_some() || mime.starts_with("text/") || mime == "image/svg+xml" {
+            return true;
+        }
+        let Some(subtype) = mime.strip_prefix("application/") else {
+            return false;
+        };
+        matches!(
+            subtype,
+            "json"
+                | "xml"
+                | "javascript"
+                | "ecmascript"
+                | "graphql"
+                | "x-ndjson"
+                | "yaml"
+                | "x-yaml"
+                | "toml"
+                | "x-www-form-urlencoded"
+        ) || subtype.ends_with("+json")
+            || subtype.ends_with("+xml")
+            || subtype.ends_with("+yaml")
+    }
+
+    /// Decodes text in its charset, UTF-8 by default. Invalid sequences
+    /// are replaced rather than failing.
+    fn decode(&self, body: &[u8]) -> String {
+        let encoding = self
+            .charset
+            .as_deref()
+            .and_then(|charset| Encoding::for_label(charset.as_bytes()))
+            .unwrap_or(UTF_8);
+        let (text, _, malformed) = encoding.decode(body);
+        if malformed {
+            debug!("Response body is not valid {}", encoding.name());
+        }
+        text.into_owned()
+    }
+}
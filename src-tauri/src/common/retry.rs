@@ -0,0 +1,287 @@
+use chrono::{DateTime, Utc};
+use log::debug;
+use rand::Rng;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use typeshare::typeshare;
+
+use crate::common::proxy_http::{HttpError, HttpHeader, HttpResponse};
+
+/// Delay before the first retry when `initial_delay_ms` is not set
+const DEFAULT_INITIAL_DELAY_MS: u32 = 500;
+/// Longest wait between attempts when `max_delay_ms` is not set
+const DEFAULT_MAX_DELAY_MS: u32 = 30_000;
+/// Statuses retried when `retry_on_status` is not set
+const DEFAULT_RETRY_ON_STATUS: [u16; 6] = [408, 429, 500, 502, 503, 504];
+
+/// When and how often a failed request is sent again. Part of
+/// `RequestOptions`, so it can be set per request or per endpoint.
+#[typeshare]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one; 1, no retries, by default
+    pub max_attempts: Option<u32>,
+    /// Delay before the first retry in milliseconds, doubled for every
+    /// further retry and randomized by up to half, 500ms by default
+    pub initial_delay_ms: Option<u32>,
+    /// Longest wait between attempts in milliseconds, 30s by default. A
+    /// `Retry-After` asking for longer ends the retries.
+    pub max_delay_ms: Option<u32>,
+    /// Response statuses that are retried; 408, 429, 500, 502, 503 and 504
+    /// by default
+    pub retry_on_status: Option<Vec<u16>>,
+    /// Whether requests that fail without a response, such as on a reset
+    /// connection or a timeout, are retried; true by default
+    pub retry_on_error: Option<bool>,
+    /// Also retry methods that are not idempotent, such as POST, which may
+    /// then be executed more than once; false by default
+    pub retry_non_idempotent: Option<bool>,
+}
+
+/// One attempt at sending a request
+#[typeshare]
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestAttempt {
+    /// Status of the response, if one was received
+    pub status_code: Option<u16>,
+    /// Why no response was received
+    pub error: Option<String>,
+    pub duration_ms: u128,
+    /// How long was waited before the next attempt; not set for the last one
+    pub delay_ms: Option<u128>,
+}
+
+impl RetryPolicy {
+    fn applies_to(&self, method: &str) -> bool {
+        let idempotent = method.parse::<Method>().is_ok_and(|method| {
+            matches!(
+                method,
+                Method::GET
+                    | Method::HEAD
+                    | Method::OPTIONS
+                    | Method::TRACE
+                    | Method::PUT
+                    | Method::DELETE
+            )
+        });
+        self.max_attempts.unwrap_or(1) > 1
+            && (idempotent || self.retry_non_idempotent.unwrap_or(false))
+    }
+
+    /// How long to wait before sending the request again after `attempt`
+    /// (counted from 1) ended with `result`, or `None` to stop.
+    fn delay(&self, attempt: u32, result: &Result<HttpResponse, HttpError>) -> Option<Duration> {
+        let retry_after = match result {
+            Ok(resp) if self.retries_status(resp.status_code) => retry_after(&resp.headers),
+            Err(e) if e.transient && self.retry_on_error.unwrap_or(true) => None,
+            _ => return None,
+        };
+        let max_delay =
+            Duration::from_millis(self.max_delay_ms.unwrap_or(DEFAULT_MAX_DELAY_MS).into());
+        if let Some(retry_after) = retry_after {
+            return (retry_after <= max_delay).then_some(retry_after);
+        }
+
+        let initial = u64::from(self.initial_delay_ms.unwrap_or(DEFAULT_INITIAL_DELAY_MS));
+        let backoff = initial
+            .saturating_mul(2u64.saturating_pow(attempt - 1))
+            .min(max_delay.as_millis().try_into().unwrap_or(u64::MAX));
+        Some(Duration::from_millis(
+            rand::rng().random_range(backoff / 2..=backoff),
+        ))
+    }
+
+    fn retries_status(&self, status_code: u16) -> bool {
+        match &self.retry_on_status {
+            Some(statuses) => statuses.contains(&status_code),
+            None => DEFAULT_RETRY_ON_STATUS.contains(&status_code),
+        }
+    }
+}
+
+/// The wait asked for by a `Retry-After` header, in seconds or as an HTTP
+/// date.
+fn retry_after(headers: &[HttpHeader]) -> Option<Duration> {
+    let value = headers
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case("retry-after"))?
+        .value
+        .trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Sends a request with `send` until it gets a response whose status is
+/// not retried, fails in a way that is not retried, or runs out of
+/// attempts. When `policy` applies to `method`, the attempts are reported
+/// in the response or error.
+pub async fn with_retries<F, Fut>(
+    policy: Option<&RetryPolicy>,
+    method: &str,
+    mut send: F,
+) -> Result<HttpResponse, HttpError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<HttpResponse, HttpError>>,
+{
+    let Some(policy) = policy.filter(|policy| policy.applies_to(method)) else {
+        return send().await;
+    };
+    let max_attempts = policy.max_attempts.unwrap_or(1);
+
+    let mut attempts = Vec::new();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let start = Instant::now();
+        let result = send().await;
+        attempts.push(RequestAttempt {
+            status_code: result.as_ref().ok().map(|resp| resp.status_code),
+            error: result.as_ref().err().map(|e| e.message.clone()),
+            duration_ms: start.elapsed().as_millis(),
+            delay_ms: None,
+        });
+
+        let delay = (attempt < max_attempts)
+            .then(|| policy.delay(attempt, &result))
+            .flatten();
+        let Some(delay) = delay else {
+            return match result {
+                Ok(mut resp) => {
+                    resp.attempts = attempts;
+                    Ok(resp)
+                }
+                Err(mut e) => {
+                    e.attempts = attempts;
+                    Err(e)
+                }
+            };
+        };
+
+        debug!(
+            "Attempt {} of {} failed, retrying in {}ms",
+            attempt,
+            max_attempts,
+            delay.as_millis()
+        );
+        if let Some(last) = attempts.last_mut() {
+            last.delay_ms = Some(delay.as_millis());
+        }
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, retry_after: Option<&str>) -> Result<HttpResponse, HttpError> {
+        let mut builder = hyper::Response::builder().status(status);
+        if let Some(retry_after) = retry_after {
+            builder = builder.header("Retry-After", retry_after);
+        }
+        let resp: reqwest::Response = builder.body("").unwrap().into();
+        Ok(HttpResponse::head(&resp))
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: Some(3),
+            initial_delay_ms: Some(1_000),
+            max_delay_ms: Some(5_000),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn retries_post_only_when_asked() {
+        let policy = policy();
+        assert!(policy.applies_to("GET"));
+        assert!(policy.applies_to("PUT"));
+        assert!(!policy.applies_to("POST"));
+        assert!(!policy.applies_to("PATCH"));
+
+        let policy = RetryPolicy {
+            retry_non_idempotent: Some(true),
+            ..policy
+        };
+        assert!(policy.applies_to("POST"));
+        assert!(!RetryPolicy::default().applies_to("GET"));
+    }
+
+    #[test]
+    fn waits_as_long_as_retry_after_asks() {
+        let policy = policy();
+        assert_eq!(
+            policy.delay(1, &response(503, Some("3"))),
+            Some(Duration::from_secs(3))
+        );
+
+        let date = (Utc::now() + chrono::Duration::seconds(4)).to_rfc2822();
+        let date = date.replace("+0000", "GMT");
+        let delay = policy.delay(1, &response(429, Some(&date))).unwrap();
+        assert!(delay > Duration::from_secs(2) && delay <= Duration::from_secs(4));
+
+        let past = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert_eq!(
+            policy.delay(1, &response(503, Some(past))),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn stops_when_retry_after_exceeds_the_longest_delay() {
+        let policy = policy();
+        assert_eq!(policy.delay(1, &response(503, Some("6"))), None);
+        assert_eq!(
+            policy.delay(1, &response(503, Some("5"))),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn caps_the_backoff_at_the_longest_delay() {
+        let policy = policy();
+        for (attempt, max) in [(1, 1_000), (2, 2_000), (3, 4_000), (4, 5_000), (40, 5_000)] {
+            let delay = policy.delay(attempt, &response(500, None)).unwrap();
+            let delay = delay.as_millis();
+            assert!(
+                (max / 2..=max).contains(&delay),
+                "attempt {} waited {}ms",
+                attempt,
+                delay
+            );
+        }
+    }
+
+    #[test]
+    fn retries_only_listed_statuses_and_transient_errors() {
+        let policy = policy();
+        assert_eq!(policy.delay(1, &response(404, None)), None);
+        assert!(policy
+            .delay(1, &Err(HttpError::transient("connection reset")))
+            .is_some());
+        assert_eq!(policy.delay(1, &Err(HttpError::new("invalid URL"))), None);
+
+        let policy = RetryPolicy {
+            retry_on_status: Some(vec![404]),
+            retry_on_error: Some(false),
+            ..policy
+        };
+        assert!(policy.delay(1, &response(404, None)).is_some());
+        assert_eq!(policy.delay(1, &response(503, None)), None);
+        assert_eq!(
+            policy.delay(1, &Err(HttpError::transient("connection reset"))),
+            None
+        );
+    }
+}
@@ -0,0 +1,556 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use log::info;
+use once_cell::sync::Lazy;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{types::Json, SqlitePool};
+use std::sync::RwLock;
+use typeshare::typeshare;
+
+use crate::common::client_certificate::ClientCertificateFormat;
+use crate::database::entities::endpoint_entity::{AuthConfig, Endpoint, EndpointConfig};
+use crate::database::repositories::{
+    EndpointCookieRepository, EndpointRepository, SecretVaultRepository,
+};
+
+/// Prefix of encrypted values, followed by base64 of nonce and ciphertext
+const SEALED_PREFIX: &str = "enc:v1:";
+/// Error of storing a value that looks encrypted but is not
+const SEALED_LOOKALIKE: &str = "A secret starts with \"enc:v1:\" but cannot be decrypted \
+     with the master password, replace it before saving";
+/// Error of operations that need to know whether secrets are encrypted
+const NOT_LOADED: &str = "Endpoint secrets are still loading, try again";
+/// Plaintext of the verifier used to check the master password
+const VERIFIER_PLAINTEXT: &str = "query-box secret vault";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+// Argon2id parameters; changing them requires a new `SEALED_PREFIX` version.
+const KDF_MEMORY_KIB: u32 = 19_456;
+const KDF_ITERATIONS: u32 = 2;
+const KDF_PARALLELISM: u32 = 1;
+
+/// Key derived from the master password
+#[derive(Clone)]
+pub struct VaultKey(Key);
+
+impl VaultKey {
+    fn derive(password: &str, salt: &[u8]) -> Result<Self, String> {
+        let params = Params::new(KDF_MEMORY_KIB, KDF_ITERATIONS, KDF_PARALLELISM, None)
+            .map_err(|e| format!("Invalid key derivation parameters: {}", e))?;
+        let mut key = Key::default();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), salt, &mut key)
+            .map_err(|e| format!("Failed to derive key from master password: {}", e))?;
+        Ok(Self(key))
+    }
+
+    fn seal(&self, plaintext: &str) -> Result<String, String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::rng().fill(&mut nonce);
+        let ciphertext = XChaCha20Poly1305::new(&self.0)
+            .encrypt(XNonce::from_slice(&nonce), plaintext.as_bytes())
+            .map_err(|_| "Failed to encrypt secret".to_string())?;
+        Ok(format!(
+            "{}{}",
+            SEALED_PREFIX,
+            STANDARD.encode([nonce.as_slice(), &ciphertext].concat())
+        ))
+    }
+
+    fn open(&self, sealed: &str) -> Result<String, String> {
+        let data = sealed
+            .strip_prefix(SEALED_PREFIX)
+            .and_then(|data| STANDARD.decode(data).ok())
+            .filter(|data| data.len() > NONCE_LEN)
+            .ok_or_else(|| "Encrypted secret is corrupt".to_string())?;
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let plaintext = XChaCha20Poly1305::new(&self.0)
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                "Failed to decrypt secret, it was encrypted with another key".to_string()
+            })?;
+        String::from_utf8(plaintext).map_err(|_| "Decrypted secret is not valid UTF-8".to_string())
+    }
+}
+
+enum VaultState {
+    /// `load` has not run yet, so whether secrets must be encrypted is
+    /// unknown and they cannot be saved
+    NotLoaded,
+    /// No master password is set, secrets are stored in plain text
+    Disabled,
+    /// Secrets are encrypted and the master password has not been entered
+    Locked,
+    /// Secrets are encrypted and decrypted with the contained key
+    Unlocked(VaultKey),
+}
+
+static STATE: Lazy<RwLock<VaultState>> = Lazy::new(|| RwLock::new(VaultState::NotLoaded));
+
+fn state() -> std::sync::RwLockReadGuard<'static, VaultState> {
+    STATE.read().expect("secret vault state poisoned")
+}
+
+fn set_state(state: VaultState) {
+    *STATE.write().expect("secret vault state poisoned") = state;
+}
+
+/// Whether endpoint secrets are encrypted and currently readable
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretVaultStatus {
+    /// A master password is set and secrets are stored encrypted
+    pub enabled: bool,
+    /// The master password has been entered in this session
+    pub unlocked: bool,
+}
+
+pub fn status() -> SecretVaultStatus {
+    match &*state() {
+        VaultState::NotLoaded => SecretVaultStatus {
+            enabled: false,
+            unlocked: false,
+        },
+        VaultState::Disabled => SecretVaultStatus {
+            enabled: false,
+            unlocked: true,
+        },
+        VaultState::Locked => SecretVaultStatus {
+            enabled: true,
+            unlocked: false,
+        },
+        VaultState::Unlocked(_) => SecretVaultStatus {
+            enabled: true,
+            unlocked: true,
+        },
+    }
+}
+
+/// Starts locked when a master password was set in a previous session.
+pub async fn load(pool: &SqlitePool) -> Result<(), String> {
+    let record = SecretVaultRepository::find(pool)
+        .await
+        .map_err(|e| format!("Failed to load secret vault: {}", e))?;
+    if record.is_some() {
+        info!("Endpoint secrets are encrypted, starting locked");
+        set_state(VaultState::Locked);
+    } else {
+        set_state(VaultState::Disabled);
+    }
+    Ok(())
+}
+
+/// Sets the master password and encrypts the secrets of all endpoints.
+pub async fn enable(pool: &SqlitePool, password: &str) -> Result<(), String> {
+    match &*state() {
+        VaultState::Disabled => {}
+        VaultState::NotLoaded => return Err(NOT_LOADED.to_string()),
+        _ => return Err("A master password is already set".to_string()),
+    }
+    let key = rekey(pool, None, password).await?;
+    set_state(VaultState::Unlocked(key));
+    info!("Endpoint secrets encrypted with the master password");
+    Ok(())
+}
+
+/// Replaces the master password, re-encrypting all endpoint secrets.
+pub async fn change_password(pool: &SqlitePool, current: &str, new: &str) -> Result<(), String> {
+    let current_key = verify(pool, current).await?;
+    let key = rekey(pool, Some(&current_key), new).await?;
+    set_state(VaultState::Unlocked(key));
+    info!("Endpoint secrets re-encrypted with the new master password");
+    Ok(())
+}
+
+/// Checks the master password and keeps its key for this session.
+pub async fn unlock(pool: &SqlitePool, password: &str) -> Result<(), String> {
+    let key = verify(pool, password).await?;
+    set_state(VaultState::Unlocked(key));
+    Ok(())
+}
+
+/// Forgets the key; encrypted secrets stay unreadable until unlocked.
+pub fn lock() -> Result<(), String> {
+    if matches!(&*state(), VaultState::NotLoaded | VaultState::Disabled) {
+        return Err("No master password is set".to_string());
+    }
+    set_state(VaultState::Locked);
+    Ok(())
+}
+
+async fn verify(pool: &SqlitePool, password: &str) -> Result<VaultKey, String> {
+    let record = SecretVaultRepository::find(pool)
+        .await
+        .map_err(|e| format!("Failed to load secret vault: {}", e))?
+        .ok_or_else(|| "No master password is set".to_string())?;
+    let salt = STANDARD
+        .decode(&record.kdf_salt)
+        .map_err(|_| "Secret vault salt is corrupt".to_string())?;
+    let key = VaultKey::derive(password, &salt)?;
+    match key.open(&record.verifier) {
+        Ok(plaintext) if plaintext == VERIFIER_PLAINTEXT => Ok(key),
+        _ => Err("Wrong master password".to_string()),
+    }
+}
+
+/// Derives a key from `password` with a fresh salt and re-encrypts every
+/// endpoint and cookie with it, in one transaction with storing the new
+/// verifier.
+async fn rekey(
+    pool: &SqlitePool,
+    current: Option<&VaultKey>,
+    password: &str,
+) -> Result<VaultKey, String> {
+    if password.is_empty() {
+        return Err("Master password must not be empty".to_string());
+    }
+    let mut salt = [0u8; SALT_LEN];
+    rand::rng().fill(&mut salt);
+    let key = VaultKey::derive(password, &salt)?;
+    let verifier = key.seal(VERIFIER_PLAINTEXT)?;
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| format!("Failed to start transaction: {}", e))?;
+    EndpointRepository::reseal_secrets(&mut tx, |auth, config| {
+        with_secrets(auth, config, |secrets| reseal(secrets, current, &key))
+    })
+    .await
+    .map_err(|e| format!("Failed to encrypt endpoint secrets: {}", e))?;
+    EndpointCookieRepository::reseal_values(&mut tx, |value| reseal(vec![value], current, &key))
+        .await
+        .map_err(|e| format!("Failed to encrypt cookies: {}", e))?;
+    SecretVaultRepository::save(&mut tx, &STANDARD.encode(salt), &verifier)
+        .await
+        .map_err(|e| format!("Failed to save secret vault: {}", e))?;
+    tx.commit()
+        .await
+        .map_err(|e| format!("Failed to save secret vault: {}", e))?;
+    Ok(key)
+}
+
+/// Passes the secret values of an endpoint to `f`: the credentials of
+/// `auth`, the private parts of a client certificate in `config`, and the
+/// custom headers of `auth`. Those may be an object, rows or a JSON string
+/// of either (see `header_pairs`), so they are handled as one value, their
+/// JSON text, which is parsed back afterwards unless it was sealed.
+fn with_secrets<T>(
+    mut auth: Option<&mut AuthConfig>,
+    config: Option<&mut EndpointConfig>,
+    f: impl FnOnce(Vec<&mut String>) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut headers = auth
+        .as_deref_mut()
+        .and_then(|auth| auth.custom_headers.take())
+        .map(|headers| match headers.0 {
+            Value::String(text) if is_sealed(&text) => text,
+            value => value.to_string(),
+        });
+
+    let mut values = secrets(auth.as_deref_mut(), config);
+    values.extend(headers.as_mut().filter(|text| text.as_str() != "null"));
+    let result = f(values);
+
+    if let (Some(auth), Some(text)) = (auth, headers) {
+        let value = match serde_json::from_str(&text) {
+            Ok(value) if !is_sealed(&text) => value,
+            _ => Value::String(text),
+        };
+        auth.custom_headers = Some(Json(value));
+    }
+    result
+}
+
+/// The secret values of an endpoint besides its custom headers, see
+/// `with_secrets`.
+fn secrets<'a>(
+    auth: Option<&'a mut AuthConfig>,
+    config: Option<&'a mut EndpointConfig>,
+) -> Vec<&'a mut String> {
+    let mut secrets = Vec::new();
+    if let Some(auth) = auth {
+        secrets.extend(
+            [
+                &mut auth.password,
+                &mut auth.token,
+                &mut auth.api_key_value,
+                &mut auth.oauth_client_secret,
+                &mut auth.oauth_refresh_token,
+                &mut auth.aws_secret_access_key,
+                &mut auth.aws_session_token,
+                &mut auth.hmac_secret,
+            ]
+            .into_iter()
+            .flatten(),
+        );
+    }
+    if let Some(certificate) = config
+        .and_then(|config| config.request_options.as_mut())
+        .and_then(|options| options.client_certificate.as_mut())
+    {
+        if certificate.format == ClientCertificateFormat::Pkcs12 {
+            secrets.extend(certificate.cert_data.as_mut());
+        }
+        secrets.extend(certificate.key_data.as_mut());
+        secrets.extend(certificate.password.as_mut());
+    }
+    secrets.retain(|secret| !secret.is_empty());
+    secrets
+}
+
+pub fn is_sealed(secret: &str) -> bool {
+    secret.starts_with(SEALED_PREFIX)
+}
+
+/// Encrypts the secrets of an endpoint about to be stored. Values that are
+/// still encrypted because they were read while locked are kept as is;
+/// other values starting with `enc:v1:` are refused.
+pub fn seal_secrets(
+    auth: Option<&mut AuthConfig>,
+    config: Option<&mut EndpointConfig>,
+) -> Result<(), String> {
+    seal_with(&state(), auth, config)
+}
+
+fn seal_with(
+    state: &VaultState,
+    auth: Option<&mut AuthConfig>,
+    config: Option<&mut EndpointConfig>,
+) -> Result<(), String> {
+    with_secrets(auth, config, |secrets| {
+        secrets
+            .into_iter()
+            .try_for_each(|secret| seal_one(state, secret))
+    })
+}
+
+fn seal_one(state: &VaultState, secret: &mut String) -> Result<(), String> {
+    match state {
+        VaultState::NotLoaded => Err(NOT_LOADED.to_string()),
+        // Nothing is encrypted without a master password.
+        VaultState::Disabled if is_sealed(secret) => Err(SEALED_LOOKALIKE.to_string()),
+        VaultState::Disabled => Ok(()),
+        VaultState::Locked if is_sealed(secret) => Ok(()),
+        VaultState::Locked => {
+            Err("Endpoint secrets are locked, unlock them to save changes".to_string())
+        }
+        // Only values that decrypt were encrypted by the vault; any other
+        // would be mistaken for an encrypted value once stored.
+        VaultState::Unlocked(key) if is_sealed(secret) => key
+            .open(secret)
+            .map(|_| ())
+            .map_err(|_| SEALED_LOOKALIKE.to_string()),
+        VaultState::Unlocked(key) => {
+            *secret = key.seal(secret)?;
+            Ok(())
+        }
+    }
+}
+
+/// Encrypts a secret kept apart from the endpoint, such as a cookie value,
+/// like `seal_secrets` does.
+pub fn seal_secret(secret: &mut String) -> Result<(), String> {
+    seal_one(&state(), secret)
+}
+
+/// Decrypts a secret of `seal_secret`. While locked it is left encrypted.
+pub fn open_secret(secret: &mut String) -> Result<(), String> {
+    if let VaultState::Unlocked(key) = &*state() {
+        if is_sealed(secret) {
+            *secret = key.open(secret)?;
+        }
+    }
+    Ok(())
+}
+
+/// Decrypts the secrets of an endpoint read from the database. While
+/// locked they are left encrypted, and so are all of them when one fails
+/// to decrypt.
+pub fn open_secrets(
+    auth: Option<&mut AuthConfig>,
+    config: Option<&mut EndpointConfig>,
+) -> Result<(), String> {
+    match &*state() {
+        VaultState::Unlocked(key) => open_with(key, auth, config),
+        _ => Ok(()),
+    }
+}
+
+fn open_with(
+    key: &VaultKey,
+    auth: Option<&mut AuthConfig>,
+    config: Option<&mut EndpointConfig>,
+) -> Result<(), String> {
+    with_secrets(auth, config, |mut secrets| {
+        let opened = secrets
+            .iter()
+            .map(|secret| match is_sealed(secret) {
+                true => key.open(secret).map(Some),
+                false => Ok(None),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for (secret, plaintext) in secrets.iter_mut().zip(opened) {
+            if let Some(plaintext) = plaintext {
+                **secret = plaintext;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Fails when the endpoint still holds encrypted secrets, which happens
+/// when it was loaded while locked or its secrets failed to decrypt.
+pub fn ensure_unlocked(endpoint: &Endpoint) -> Result<(), String> {
+    let mut endpoint = endpoint.clone();
+    let sealed = with_secrets(
+        endpoint.auth.as_mut(),
+        endpoint.config.as_mut(),
+        |secrets| Ok(secrets.iter().any(|secret| is_sealed(secret))),
+    )?;
+    if !sealed {
+        return Ok(());
+    }
+    match &*state() {
+        VaultState::Unlocked(_) => Err(format!(
+            "Secrets of endpoint '{}' could not be decrypted, enter them again",
+            endpoint.name
+        )),
+        _ => Err(format!(
+            "Secrets of endpoint '{}' are locked, enter the master password to use it",
+            endpoint.name
+        )),
+    }
+}
+
+fn reseal(
+    secrets: Vec<&mut String>,
+    current: Option<&VaultKey>,
+    key: &VaultKey,
+) -> Result<(), String> {
+    for secret in secrets {
+        // Without a master password nothing is encrypted, whatever it looks like.
+        let plaintext = match current {
+            Some(current) if is_sealed(secret) => current.open(secret)?,
+            _ => secret.clone(),
+        };
+        *secret = key.seal(&plaintext)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn key(byte: u8) -> VaultKey {
+        VaultKey(Key::from([byte; 32]))
+    }
+
+    fn auth(custom_headers: Value) -> AuthConfig {
+        serde_json::from_value(json!({
+            "auth_type": "custom",
+            "token": "t0ken",
+            "custom_headers": custom_headers,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn seals_custom_headers_of_every_shape() {
+        let key = key(1);
+        let state = VaultState::Unlocked(key.clone());
+        for headers in [
+            json!({ "X-Api-Key": "s3cret" }),
+            json!([{ "key": "X-Api-Key", "value": "s3cret" }]),
+            json!("[{\"key\":\"X-Api-Key\",\"value\":\"s3cret\"}]"),
+        ] {
+            let mut auth = auth(headers.clone());
+            seal_with(&state, Some(&mut auth), None).unwrap();
+            let stored = serde_json::to_string(&auth).unwrap();
+            assert!(!stored.contains("s3cret"), "{}", stored);
+            assert!(!stored.contains("t0ken"), "{}", stored);
+
+            // Sealing again keeps the encrypted values.
+            seal_with(&state, Some(&mut auth), None).unwrap();
+            open_with(&key, Some(&mut auth), None).unwrap();
+            assert_eq!(auth.custom_headers.unwrap().0, headers);
+            assert_eq!(auth.token.as_deref(), Some("t0ken"));
+        }
+    }
+
+    #[test]
+    fn refuses_to_store_secrets_before_loading() {
+        let mut auth = auth(json!({ "X-Api-Key": "s3cret" }));
+        assert_eq!(
+            seal_with(&VaultState::NotLoaded, Some(&mut auth), None).unwrap_err(),
+            NOT_LOADED
+        );
+        assert!(seal_with(&VaultState::Locked, Some(&mut auth), None).is_err());
+
+        seal_with(&VaultState::Disabled, Some(&mut auth), None).unwrap();
+        assert_eq!(auth.token.as_deref(), Some("t0ken"));
+        assert_eq!(
+            auth.custom_headers.unwrap().0,
+            json!({ "X-Api-Key": "s3cret" })
+        );
+    }
+
+    #[test]
+    fn leaves_all_secrets_sealed_when_one_fails_to_open() {
+        let (key, other) = (key(1), key(2));
+        let mut auth = auth(json!({ "X-Api-Key": "s3cret" }));
+        seal_with(&VaultState::Unlocked(key.clone()), Some(&mut auth), None).unwrap();
+        auth.password = Some(other.seal("pa55").unwrap());
+        let sealed = serde_json::to_value(&auth).unwrap();
+
+        assert!(open_with(&key, Some(&mut auth), None).is_err());
+        assert_eq!(serde_json::to_value(&auth).unwrap(), sealed);
+    }
+
+    #[test]
+    fn seals_secrets_kept_apart_from_endpoints() {
+        let key = key(1);
+        let mut value = "abc123".to_string();
+        assert_eq!(
+            seal_one(&VaultState::NotLoaded, &mut value).unwrap_err(),
+            NOT_LOADED
+        );
+        seal_one(&VaultState::Disabled, &mut value).unwrap();
+        assert_eq!(value, "abc123");
+
+        seal_one(&VaultState::Unlocked(key.clone()), &mut value).unwrap();
+        assert!(is_sealed(&value));
+        assert!(seal_one(&VaultState::Locked, &mut value).is_ok());
+        assert_eq!(key.open(&value).unwrap(), "abc123");
+    }
+
+    #[test]
+    fn refuses_plaintext_that_looks_encrypted() {
+        let (key, other) = (key(1), key(2));
+        let state = VaultState::Unlocked(key.clone());
+        for lookalike in ["enc:v1:abc".to_string(), other.seal("pa55").unwrap()] {
+            let mut value = lookalike.clone();
+            assert_eq!(seal_one(&state, &mut value).unwrap_err(), SEALED_LOOKALIKE);
+            assert!(seal_one(&VaultState::Disabled, &mut value).is_err());
+            assert_eq!(value, lookalike);
+        }
+
+        let sealed = key.seal("abc123").unwrap();
+        let mut value = sealed.clone();
+        seal_one(&state, &mut value).unwrap();
+        assert_eq!(value, sealed);
+
+        // Stored before such values were refused, it is encrypted as is.
+        let mut value = "enc:v1:abc".to_string();
+        reseal(vec![&mut value], None, &key).unwrap();
+        assert_eq!(key.open(&value).unwrap(), "enc:v1:abc");
+    }
+}
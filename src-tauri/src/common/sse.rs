@@ -0,0 +1,100 @@
+/// A dispatched Server-Sent Event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    /// Event type, `"message"` when the server did not name it
+    pub event: String,
+    /// Data lines joined with `\n`
+    pub data: String,
+    /// Last event id, if the server sent one
+    pub id: Option<String>,
+}
+
+/// Incremental parser for `text/event-stream` bodies following the HTML
+/// event stream interpretation rules, except that named events are
+/// dispatched even without data (graphql-sse sends `complete` that way).
+/// Chunks can split lines and UTF-8 sequences anywhere; incomplete lines
+/// are kept until the next chunk.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+    id: Option<String>,
+    /// A chunk ended with `\r`, so a leading `\n` in the next one belongs to it
+    pending_cr: bool,
+}
+
+impl SseParser {
+    /// Feeds a chunk of the body and returns the events it completed.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+        let mut chunk = chunk;
+
+        if self.pending_cr && chunk.first() == Some(&b'\n') {
+            chunk = &chunk[1..];
+        }
+        self.pending_cr = false;
+        self.buffer.extend_from_slice(chunk);
+
+        let mut start = 0;
+        let mut i = 0;
+        while i < self.buffer.len() {
+            match self.buffer[i] {
+                b'\n' | b'\r' => {
+                    let line = String::from_utf8_lossy(&self.buffer[start..i]).into_owned();
+                    if self.buffer[i] == b'\r' {
+                        match self.buffer.get(i + 1) {
+                            Some(b'\n') => i += 1,
+                            None => self.pending_cr = true,
+                            _ => {}
+                        }
+                    }
+                    i += 1;
+                    start = i;
+                    if let Some(event) = self.process_line(&line) {
+                        events.push(event);
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+        self.buffer.drain(..start);
+
+        events
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => self.data.push(value.to_string()),
+            "id" if !value.contains('\0') => self.id = Some(value.to_string()),
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if self.data.is_empty() && event.is_none() {
+            return None;
+        }
+        Some(SseEvent {
+            event: event
+                .filter(|event| !event.is_empty())
+                .unwrap_or_else(|| "message".to_string()),
+            data: std::mem::take(&mut self.data).join("\n"),
+            id: self.id.clone(),
+        })
+    }
+}
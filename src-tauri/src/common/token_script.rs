@@ -0,0 +1,356 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::debug;
+use reqwest::Url;
+use rquickjs::{
+    async_with, prelude::Async, AsyncContext, AsyncRuntime, CatchResultExt, FromJs, Function, Value,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::common::http_client::RequestOptions;
+use crate::common::proxy_http::{proxy_http, HttpHeader, HttpRequest};
+use crate::common::request_body::HttpBody;
+use crate::common::response_body::BodyEncoding;
+use crate::database::entities::endpoint_entity::{AuthConfig, AuthType};
+
+/// How long a token script may run, including the requests it makes
+const SCRIPT_TIME_LIMIT: Duration = Duration::from_secs(10);
+/// Heap available to a token script
+const SCRIPT_MEMORY_LIMIT: usize = 32 * 1024 * 1024;
+/// How long a token is reused when `token_script_ttl_secs` is not set
+const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+/// Globals available to token scripts. The engine itself has no I/O, so
+/// `fetch` is the only way out and goes through `__send`, which performs
+/// the request with `proxy_http` using the endpoint's network options.
+const PRELUDE: &str = r#"
+globalThis.fetch = async (input, init = {}) => {
+  const entries = Array.isArray(init.headers) ? init.headers : Object.entries(init.headers ?? {});
+  const headers = entries.map(([name, value]) => ({ name: String(name), value: String(value) }));
+  const reply = JSON.parse(await __send(JSON.stringify({
+    method: String(init.method ?? 'GET').toUpperCase(),
+    url: String(input),
+    headers,
+    body: init.body == null ? null : String(init.body),
+  })));
+  if (reply.error !== undefined) throw new TypeError(reply.error);
+  const responseHeaders = {};
+  for (const { name, value } of reply.headers) {
+    const key = name.toLowerCase();
+    responseHeaders[key] = key in responseHeaders ? `${responseHeaders[key]}, ${value}` : value;
+  }
+  return {
+    status: reply.status_code,
+    statusText: reply.status_text,
+    ok: reply.status_code >= 200 && reply.status_code < 300,
+    headers: { get: (name) => responseHeaders[String(name).toLowerCase()] ?? null },
+    text: async () => reply.body,
+    json: async () => JSON.parse(reply.body),
+  };
+};
+globalThis.console = {
+  log: (...args) => __log(args.map(String).join(' ')),
+};
+console.info = console.warn = console.error = console.debug = console.log;
+"#;
+
+/// Request passed from the `fetch` shim to `__send`
+#[derive(Deserialize)]
+struct ScriptRequest {
+    method: String,
+    url: String,
+    headers: Vec<HttpHeader>,
+    body: Option<String>,
+}
+
+struct ScriptToken {
+    /// Script that produced the token; editing it discards the token
+    script: String,
+    token: String,
+    expires_at: Instant,
+}
+
+/// Token of one endpoint. Its lock is held while the script runs so
+/// concurrent requests to the endpoint share one run, without waiting for
+/// the scripts of other endpoints.
+type TokenSlot = Arc<Mutex<Option<ScriptToken>>>;
+
+/// Tokens produced by the `token_script` of endpoints, keyed by endpoint
+/// id. Held by `OAuthTokenCache`.
+#[derive(Default)]
+pub struct TokenScriptCache {
+    tokens: std::sync::Mutex<HashMap<String, TokenSlot>>,
+}
+
+impl TokenScriptCache {
+    /// Sets the credential of `auth` to the token returned by its script,
+    /// when it has one: the bearer token, or the API key value. Tokens are
+    /// reused for `token_script_ttl_secs`.
+    pub async fn authorize(
+        &self,
+        endpoint_id: &str,
+        auth: &mut AuthConfig,
+        options: Option<RequestOptions>,
+    ) -> Result<(), String> {
+        let Some(script) = token_script(auth) else {
+            return Ok(());
+        };
+
+        let slot = self.slot(endpoint_id);
+        let mut slot = slot.lock().await;
+        let token = match slot.as_ref() {
+            Some(cached) if cached.script == script && Instant::now() < cached.expires_at => {
+                cached.token.clone()
+            }
+            _ => {
+                debug!("Running token script of endpoint {}", endpoint_id);
+                let token = run_token_script(script, options)
+                    .await
+                    .map_err(|e| format!("Token script failed: {}", e))?;
+                let ttl = auth
+                    .token_script_ttl_secs
+                    .map_or(DEFAULT_TTL, |ttl| Duration::from_secs(ttl.into()));
+                *slot = Some(ScriptToken {
+                    script: script.to_string(),
+                    token: token.clone(),
+                    expires_at: Instant::now() + ttl,
+                });
+                token
+            }
+        };
+
+        match auth.auth_type {
+            AuthType::ApiKey => auth.api_key_value = Some(token),
+            _ => auth.token = Some(token),
+        }
+        Ok(())
+    }
+
+    /// Forgets the token of an endpoint so its script runs again.
+    pub async fn invalidate(&self, endpoint_id: &str) {
+        self.lock_tokens().remove(endpoint_id);
+    }
+
+    pub async fn clear(&self) {
+        self.lock_tokens().clear();
+    }
+
+    fn slot(&self, endpoint_id: &str) -> TokenSlot {
+        self.lock_tokens()
+            .entry(endpoint_id.to_string())
+            .or_default()
+            .clone()
+    }
+
+    fn lock_tokens(&self) -> std::sync::MutexGuard<'_, HashMap<String, TokenSlot>> {
+        self.tokens.lock().expect("token script cache poisoned")
+    }
+}
+
+/// The script of an endpoint whose credential it produces; scripts are
+/// used with bearer and API key authentication.
+pub fn token_script(auth: &AuthConfig) -> Option<&str> {
+    if !matches!(auth.auth_type, AuthType::Bearer | AuthType::ApiKey) {
+        return None;
+    }
+    auth.token_script
+        .as_deref()
+        .filter(|script| !script.trim().is_empty())
+}
+
+/// Evaluates a script of the form `async () => string` in a fresh
+/// QuickJS context and returns the resolved token.
+pub async fn run_token_script(
+    script: &str,
+    options: Option<RequestOptions>,
+) -> Result<String, String> {
+    tokio::time::timeout(SCRIPT_TIME_LIMIT, evaluate(script, options))
+        .await
+        .map_err(|_| format!("Timed out after {} seconds", SCRIPT_TIME_LIMIT.as_secs()))?
+}
+
+async fn evaluate(script: &str, options: Option<RequestOptions>) -> Result<String, String> {
+    let runtime = AsyncRuntime::new().map_err(|e| e.to_string())?;
+    runtime.set_memory_limit(SCRIPT_MEMORY_LIMIT).await;
+    // Stops scripts that never yield, which the timeout above cannot.
+    let deadline = Instant::now() + SCRIPT_TIME_LIMIT;
+    runtime
+        .set_interrupt_handler(Some(Box::new(move || Instant::now() > deadline)))
+        .await;
+    let context = AsyncContext::full(&runtime)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let source = format!("({})()", script.trim().trim_end_matches(';'));
+    async_with!(context => |ctx| {
+        let send = Function::new(
+            ctx.clone(),
+            Async(move |request: String| {
+                let options = options.clone();
+                async move { Ok::<_, rquickjs::Error>(send(&request, options).await) }
+            }),
+        )
+        .map_err(|e| e.to_string())?;
+        let log = Function::new(ctx.clone(), |message: String| {
+            debug!("Token script: {}", message);
+        })
+        .map_err(|e| e.to_string())?;
+        ctx.globals().set("__send", send).map_err(|e| e.to_string())?;
+        ctx.globals().set("__log", log).map_err(|e| e.to_string())?;
+        ctx.eval::<(), _>(PRELUDE).map_err(|e| e.to_string())?;
+
+        let result: Value = ctx
+            .eval(source)
+            .catch(&ctx)
+            .map_err(|e| e.to_string().trim_end().to_string())?;
+        let result: Value = match result.as_promise() {
+            Some(promise) => promise
+                .clone()
+                .into_future()
+                .await
+                .catch(&ctx)
+                .map_err(|e| e.to_string().trim_end().to_string())?,
+            None => result,
+        };
+        if !result.is_string() {
+            return Err(format!(
+                "Expected the script to return a string, got {}",
+                result.type_name()
+            ));
+        }
+        let token = String::from_js(&ctx, result).map_err(|e| e.to_string())?;
+        if token.trim().is_empty() {
+            return Err("The script returned an empty token".to_string());
+        }
+        Ok(token.trim().to_string())
+    })
+    .await
+}
+
+/// Performs a request of the `fetch` shim and returns the response, or
+/// the error, as JSON. Only http(s) URLs can be fetched, and binary bodies
+/// are passed to the script as text.
+async fn send(request: &str, options: Option<RequestOptions>) -> String {
+    let error = |message: String| json!({ "error": message }).to_string();
+    let request: ScriptRequest = match serde_json::from_str(request) {
+        Ok(request) => request,
+        Err(e) => return error(format!("Invalid request: {}", e)),
+    };
+    match Url::parse(&request.url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+        Ok(url) => return error(format!("Unsupported URL scheme: {}", url.scheme())),
+        Err(e) => return error(format!("Invalid URL {}: {}", request.url, e)),
+    }
+
+    let response = proxy_http(HttpRequest {
+        method: request.method,
+        url: request.url,
+        headers: Some(request.headers),
+        body: request.body.map(HttpBody::Text),
+        options,
+        endpoint_id: None,
+        signing: None,
+        cookie_jar: None,
+    })
+    .await;
+    let mut response = match response {
+        Ok(response) => response,
+        Err(e) => return error(e.message),
+    };
+    match response.body_encoding {
+        BodyEncoding::Text => {}
+        BodyEncoding::Base64 => {
+            let bytes = STANDARD.decode(&response.body).unwrap_or_default();
+            response.body = String::from_utf8_lossy(&bytes).into_owned();
+            response.body_encoding = BodyEncoding::Text;
+        }
+        BodyEncoding::File => {
+            if let Err(e) = tokio::fs::remove_file(&response.body).await {
+                debug!("Failed to remove {}: {}", response.body, e);
+            }
+            return error("The response body is too large for a token script".to_string());
+        }
+    }
+    serde_json::to_string(&response).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serves `body` as `application/octet-stream` to every connection.
+    async fn binary_server(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 4096];
+                let _ = stream.read(&mut request).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    fn fetching(url: &str) -> String {
+        format!(
+            "async () => {{
+                try {{
+                    return await (await fetch('{}')).text();
+                }} catch (e) {{
+                    return 'error: ' + e.message;
+                }}
+            }}",
+            url
+        )
+    }
+
+    #[tokio::test]
+    async fn fetches_only_http_urls() {
+        for (url, scheme) in [
+            ("unix:///tmp/app.sock", "unix"),
+            ("file:///etc/hosts", "file"),
+        ] {
+            assert_eq!(
+                run_token_script(&fetching(url), None).await.unwrap(),
+                format!("error: Unsupported URL scheme: {}", scheme)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn passes_binary_bodies_as_text() {
+        let url = binary_server("s3cret-token").await;
+        assert_eq!(
+            run_token_script(&fetching(&url), None).await.unwrap(),
+            "s3cret-token"
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_bodies_written_to_files() {
+        let url = binary_server("s3cret-token").await;
+        let options = RequestOptions {
+            max_body_bytes: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            run_token_script(&fetching(&url), Some(options))
+                .await
+                .unwrap(),
+            "error: The response body is too large for a token script"
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use typeshare::typeshare;
+
+/// A cookie set by the server of an endpoint, sent back on later requests
+/// to matching URLs
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct EndpointCookie {
+    /// Unique identifier of the cookie
+    pub id: String,
+    /// Endpoint whose requests received and send the cookie
+    pub endpoint_id: String,
+    /// Cookie name
+    pub name: String,
+    /// Cookie value, stored encrypted when a master password is set and
+    /// left encrypted while the vault is locked
+    pub value: String,
+    /// Host the cookie is sent to, lowercase and without a leading dot
+    pub domain: String,
+    /// Only sent to `domain` itself, not to its subdomains, because the
+    /// server did not set the `Domain` attribute
+    pub host_only: bool,
+    /// Only sent to URLs below this path
+    pub path: String,
+    /// RFC 3339 time after which the cookie is discarded; session cookies
+    /// have none and are kept until cleared
+    pub expires_at: Option<String>,
+    /// Only sent over HTTPS (or to localhost)
+    pub secure: bool,
+    /// Not readable by scripts in a browser; informational here
+    pub http_only: bool,
+    /// `SameSite` attribute as sent by the server; informational here
+    pub same_site: Option<String>,
+    /// Timestamp when the cookie was first stored
+    pub created_at: String,
+    /// Timestamp when the cookie was last updated
+    pub updated_at: String,
+}
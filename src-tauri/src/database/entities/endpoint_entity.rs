@@ -7,6 +7,10 @@ use strum_macros::{Display, EnumString};
 use typeshare::typeshare;
 use uuid::Uuid;
 
+use crate::common::http_client::RequestOptions;
+use crate::common::request_signing::HmacAlgorithm;
+use crate::common::secret_vault;
+
 /// Type of API endpoint
 #[typeshare]
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Display, EnumString)]
@@ -40,6 +44,8 @@ pub enum AuthType {
     None,
     /// Username and password authentication
     Basic,
+    /// Username and password answering HTTP Digest challenges
+    Digest,
     /// Token-based authentication using Bearer scheme
     Bearer,
     /// Authentication using API key
@@ -48,6 +54,10 @@ pub enum AuthType {
     OAuth2,
     /// Custom authentication method
     Custom,
+    /// Requests signed with AWS Signature Version 4
+    AwsSigV4,
+    /// Requests signed with an HMAC of a shared secret
+    Hmac,
 }
 
 /// Authentication configuration for an endpoint
@@ -56,9 +66,9 @@ pub enum AuthType {
 pub struct AuthConfig {
     /// Type of authentication to use
     pub auth_type: AuthType,
-    /// Username for Basic auth or OAuth flows
+    /// Username for Basic or Digest auth, or OAuth flows
     pub username: Option<String>,
-    /// Password for Basic auth
+    /// Password for Basic or Digest auth
     pub password: Option<String>,
     /// Token value for Bearer auth
     pub token: Option<String>,
@@ -74,12 +84,51 @@ pub struct AuthConfig {
     pub oauth_client_id: Option<String>,
     /// Client secret for OAuth2 flow
     pub oauth_client_secret: Option<String>,
+    /// Space-separated scopes requested when obtaining a token
+    pub oauth_scope: Option<String>,
+    /// Refresh token used to renew the access token when present
+    pub oauth_refresh_token: Option<String>,
+    /// Authorization URL; when set, tokens are obtained interactively with
+    /// the authorization-code flow instead of the client-credentials grant
+    pub oauth_authorize_url: Option<String>,
+    /// Audience of the requested token, for providers that need one
+    pub oauth_audience: Option<String>,
+    /// Fixed loopback port for the redirect URI
+    /// `http://127.0.0.1:<port>/callback`, for providers that require the
+    /// exact URI to be registered; any free port is used otherwise
+    pub oauth_redirect_port: Option<u16>,
+    /// RFC 3339 timestamp of when `token` expires, if known
+    pub oauth_token_expires_at: Option<String>,
     /// Additional custom headers for authentication
     pub custom_headers: Option<Json<serde_json::Value>>,
     /// Custom function to dynamically generate auth token
     /// Expected to return a Promise that resolves to a string token value
     /// Function signature: async () => string
+    /// Used as the bearer token or API key value; runs in a sandbox where
+    /// `fetch` is the only I/O
     pub token_script: Option<String>,
+    /// How long the token of `token_script` is reused, in seconds; 300 by default
+    pub token_script_ttl_secs: Option<u32>,
+    /// AWS region for SigV4 signing, e.g. `us-east-1`
+    pub aws_region: Option<String>,
+    /// AWS service for SigV4 signing, e.g. `appsync` or `execute-api`
+    pub aws_service: Option<String>,
+    /// AWS access key id for SigV4 signing
+    pub aws_access_key_id: Option<String>,
+    /// AWS secret access key for SigV4 signing
+    pub aws_secret_access_key: Option<String>,
+    /// Session token of temporary AWS credentials
+    pub aws_session_token: Option<String>,
+    /// Hash function of HMAC signatures, SHA-256 by default
+    pub hmac_algorithm: Option<HmacAlgorithm>,
+    /// Shared secret of HMAC signatures
+    pub hmac_secret: Option<String>,
+    /// Key id available to the header template as `{key_id}`
+    pub hmac_key_id: Option<String>,
+    /// Headers carrying the HMAC signature, one `Name: value` per line,
+    /// with `{signature}`, `{signature_base64}`, `{timestamp}` and
+    /// `{key_id}` placeholders
+    pub hmac_header_template: Option<String>,
 }
 
 /// GraphQL-specific configuration
@@ -88,20 +137,52 @@ pub struct AuthConfig {
 pub struct GraphQLConfig {
     /// Whether schema introspection is enabled for this endpoint
     pub introspection_enabled: bool,
-    /// Cached schema from previous introspection query
+    /// Cached schema from previous introspection query, stored apart from
+    /// the config and written only by `introspect_endpoint`
     pub schema_cache: Option<String>,
     /// Default headers to include in GraphQL requests
     pub default_headers: Option<Json<serde_json::Value>>,
     /// WebSocket URL for GraphQL subscriptions
     pub subscription_url: Option<String>,
+    /// RFC 3339 timestamp of when `schema_cache` was fetched
+    pub schema_cached_at: Option<String>,
+    /// Send the `Apollo-Require-Preflight` header, which servers with CSRF
+    /// prevention require for file uploads and GET requests
+    pub require_preflight: Option<bool>,
+}
+
+impl Default for GraphQLConfig {
+    fn default() -> Self {
+        Self {
+            introspection_enabled: true,
+            schema_cache: None,
+            default_headers: None,
+            subscription_url: None,
+            schema_cached_at: None,
+            require_preflight: None,
+        }
+    }
 }
 
 /// Combined configuration for all endpoint types
 #[typeshare]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct EndpointConfig {
     /// GraphQL-specific configuration, present when endpoint_type is GraphQL
     pub graphql: Option<GraphQLConfig>,
+    /// Default network options for requests to this endpoint
+    pub request_options: Option<RequestOptions>,
+}
+
+impl EndpointConfig {
+    /// Drops the schema cache before the config is stored; it lives in
+    /// columns of its own.
+    pub fn clear_schema_cache(&mut self) {
+        if let Some(graphql) = &mut self.graphql {
+            graphql.schema_cache = None;
+            graphql.schema_cached_at = None;
+        }
+    }
 }
 
 /// Main endpoint entity representing a remote API endpoint
@@ -152,6 +233,8 @@ pub struct EndpointRow {
     tags: Option<String>,
     created_at: String,
     updated_at: String,
+    schema_cache: Option<String>,
+    schema_cached_at: Option<String>,
 }
 
 impl TryFrom<EndpointRow> for Endpoint {
@@ -176,7 +259,7 @@ impl TryFrom<EndpointRow> for Endpoint {
             EndpointStatus::Active
         });
 
-        let auth: Option<AuthConfig> = if let Some(json) = row.auth {
+        let mut auth: Option<AuthConfig> = if let Some(json) = row.auth {
             match serde_json::from_str(&json) {
                 Ok(auth) => Some(auth),
                 Err(e) => {
@@ -188,7 +271,7 @@ impl TryFrom<EndpointRow> for Endpoint {
             None
         };
 
-        let config: Option<EndpointConfig> = if row.config.trim().is_empty() {
+        let mut config: Option<EndpointConfig> = if row.config.trim().is_empty() {
             debug!("Empty config for endpoint {}", row.id);
             None
         } else {
@@ -200,17 +283,22 @@ impl TryFrom<EndpointRow> for Endpoint {
                         row.id, e
                     );
                     Some(EndpointConfig {
-                        graphql: Some(GraphQLConfig {
-                            introspection_enabled: true,
-                            schema_cache: None,
-                            default_headers: None,
-                            subscription_url: None,
-                        }),
+                        graphql: Some(GraphQLConfig::default()),
+                        request_options: None,
                     })
                 }
             }
         };
 
+        if let Some(schema_cache) = row.schema_cache {
+            let graphql = config
+                .get_or_insert_with(EndpointConfig::default)
+                .graphql
+                .get_or_insert_with(GraphQLConfig::default);
+            graphql.schema_cache = Some(schema_cache);
+            graphql.schema_cached_at = row.schema_cached_at;
+        }
+
         let headers = if let Some(json) = row.headers {
             match serde_json::from_str(&json) {
                 Ok(headers) => Some(sqlx::types::Json(headers)),
@@ -223,6 +311,12 @@ impl TryFrom<EndpointRow> for Endpoint {
             None
         };
 
+        // Secrets that fail to decrypt stay encrypted, so the endpoint is
+        // still listed and can be fixed; using it reports the failure.
+        if let Err(e) = secret_vault::open_secrets(auth.as_mut(), config.as_mut()) {
+            error!("Failed to decrypt secrets of endpoint {}: {}", row.id, e);
+        }
+
         let tags = if let Some(tags_str) = row.tags {
             match serde_json::from_str(&tags_str) {
                 Ok(tags) => Some(tags),
@@ -1,3 +1,5 @@
+pub mod endpoint_cookie_entity;
 pub mod endpoint_entity;
 pub mod request_history_entity;
+pub mod secret_vault_entity;
 pub mod settings_entity;
@@ -6,6 +6,7 @@ use typeshare::typeshare;
 use uuid::Uuid;
 
 use crate::common::http_method::HttpMethod;
+use crate::common::http_timing::HttpTimings;
 
 #[typeshare]
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,6 +29,8 @@ pub struct RequestHistory {
     pub body: Option<Json<serde_json::Value>>,
     /// Request query
     pub query: Option<String>,
+    /// Timing breakdown of the last time the request was executed
+    pub timings: Option<HttpTimings>,
     /// Timestamp when the request was made
     pub created_at: String,
     /// Timestamp when the request was last updated
@@ -45,6 +48,7 @@ pub struct RequestHistoryRow {
     pub headers: Option<String>,
     pub body: Option<String>,
     pub query: Option<String>,
+    pub timings: Option<String>,
     pub created_at: String,
     pub updated_at: String,
 }
@@ -68,6 +72,12 @@ impl TryFrom<RequestHistoryRow> for RequestHistory {
             None
         };
 
+        let timings = if let Some(timings) = row.timings {
+            Some(serde_json::from_str(&timings)?)
+        } else {
+            None
+        };
+
         Ok(Self {
             id: Uuid::parse_str(&row.id)?,
             endpoint_id: Uuid::parse_str(&row.endpoint_id)?,
@@ -78,6 +88,7 @@ impl TryFrom<RequestHistoryRow> for RequestHistory {
             headers,
             body,
             query: row.query,
+            timings,
             created_at: row.created_at,
             updated_at: row.updated_at,
         })
@@ -0,0 +1,10 @@
+use sqlx::FromRow;
+
+/// Database row holding what is needed to check the master password
+#[derive(Debug, FromRow)]
+pub struct SecretVaultRecord {
+    /// Base64 salt of the Argon2id key derivation
+    pub kdf_salt: String,
+    /// A known value encrypted with the derived key
+    pub verifier: String,
+}
@@ -0,0 +1,255 @@
+use log::{debug, error, info, warn};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use uuid::Uuid;
+
+use crate::common::secret_vault;
+use crate::database::entities::endpoint_cookie_entity::EndpointCookie;
+use crate::models::endpoint_cookie_model::SaveEndpointCookieDto;
+
+pub struct EndpointCookieRepository;
+
+impl EndpointCookieRepository {
+    /// Cookies of an endpoint that have not expired at `now`, an RFC 3339
+    /// UTC time in the format of `expires_at`. Values that cannot be
+    /// decrypted are left encrypted, see `secret_vault::open_secret`.
+    pub async fn find_by_endpoint(
+        pool: &SqlitePool,
+        endpoint_id: &str,
+        now: &str,
+    ) -> Result<Vec<EndpointCookie>, sqlx::Error> {
+        debug!("Fetching cookies of endpoint {}", endpoint_id);
+
+        sqlx::query_as!(
+            EndpointCookie,
+            r#"
+            SELECT
+                id AS "id!", endpoint_id, name, value, domain,
+                host_only AS "host_only: bool", path, expires_at,
+                secure AS "secure: bool", http_only AS "http_only: bool", same_site,
+                created_at AS "created_at!", updated_at AS "updated_at!"
+            FROM endpoint_cookie
+            WHERE endpoint_id = ? AND (expires_at IS NULL OR expires_at > ?)
+            ORDER BY domain, path, name
+            "#,
+            endpoint_id,
+            now
+        )
+        .fetch_all(pool)
+        .await
+        .map(|cookies| cookies.into_iter().map(open_value).collect())
+    }
+
+    /// Updates the cookie `dto.id`, or stores a new one replacing any with
+    /// the same name, domain and path. The value is stored encrypted when
+    /// a master password is set.
+    pub async fn save(
+        pool: &SqlitePool,
+        mut dto: SaveEndpointCookieDto,
+    ) -> Result<EndpointCookie, anyhow::Error> {
+        secret_vault::seal_secret(&mut dto.value).map_err(anyhow::Error::msg)?;
+        let path = dto.path.unwrap_or_else(|| "/".to_string());
+        let host_only = dto.host_only.unwrap_or(true);
+        let secure = dto.secure.unwrap_or(false);
+        let http_only = dto.http_only.unwrap_or(false);
+
+        let mut tx = pool.begin().await?;
+        let id = match dto.id {
+            Some(id) => {
+                debug!("Updating cookie {} of endpoint {}", id, dto.endpoint_id);
+                let result = sqlx::query!(
+                    r#"
+                    UPDATE endpoint_cookie SET
+                        name = ?, value = ?, domain = ?, host_only = ?, path = ?,
+                        expires_at = ?, secure = ?, http_only = ?, same_site = ?,
+                        updated_at = CURRENT_TIMESTAMP
+                    WHERE id = ? AND endpoint_id = ?
+                    "#,
+                    dto.name,
+                    dto.value,
+                    dto.domain,
+                    host_only,
+                    path,
+                    dto.expires_at,
+                    secure,
+                    http_only,
+                    dto.same_site,
+                    id,
+                    dto.endpoint_id
+                )
+                .execute(&mut *tx)
+                .await?;
+                if result.rows_affected() == 0 {
+                    warn!("Cookie {} not found", id);
+                    return Err(anyhow::Error::msg(format!("Cookie not found: {}", id)));
+                }
+                id
+            }
+            None => {
+                debug!(
+                    "Storing cookie {} for {}{} of endpoint {}",
+                    dto.name, dto.domain, path, dto.endpoint_id
+                );
+                let new_id = Uuid::new_v4().to_string();
+                sqlx::query_scalar!(
+                    r#"
+                    INSERT INTO endpoint_cookie (
+                        id, endpoint_id, name, value, domain, host_only, path,
+                        expires_at, secure, http_only, same_site
+                    )
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                    ON CONFLICT (endpoint_id, name, domain, path) DO UPDATE SET
+                        value = excluded.value,
+                        host_only = excluded.host_only,
+                        expires_at = excluded.expires_at,
+                        secure = excluded.secure,
+                        http_only = excluded.http_only,
+                        same_site = excluded.same_site,
+                        updated_at = CURRENT_TIMESTAMP
+                    RETURNING id AS "id!"
+                    "#,
+                    new_id,
+                    dto.endpoint_id,
+                    dto.name,
+                    dto.value,
+                    dto.domain,
+                    host_only,
+                    path,
+                    dto.expires_at,
+                    secure,
+                    http_only,
+                    dto.same_site
+                )
+                .fetch_one(&mut *tx)
+                .await?
+            }
+        };
+
+        let cookie = sqlx::query_as!(
+            EndpointCookie,
+            r#"
+            SELECT
+                id AS "id!", endpoint_id, name, value, domain,
+                host_only AS "host_only: bool", path, expires_at,
+                secure AS "secure: bool", http_only AS "http_only: bool", same_site,
+                created_at AS "created_at!", updated_at AS "updated_at!"
+            FROM endpoint_cookie
+            WHERE id = ?
+            "#,
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(open_value(cookie))
+    }
+
+    /// Removes the cookie with the given name, domain and path, which is
+    /// how servers delete a cookie.
+    pub async fn remove(
+        pool: &SqlitePool,
+        endpoint_id: &str,
+        name: &str,
+        domain: &str,
+        path: &str,
+    ) -> Result<(), sqlx::Error> {
+        debug!(
+            "Removing cookie {} for {}{} of endpoint {}",
+            name, domain, path, endpoint_id
+        );
+
+        sqlx::query!(
+            "DELETE FROM endpoint_cookie WHERE endpoint_id = ? AND name = ? AND domain = ? AND path = ?",
+            endpoint_id,
+            name,
+            domain,
+            path
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Removes the cookies of an endpoint that expired before `now`.
+    pub async fn delete_expired(
+        pool: &SqlitePool,
+        endpoint_id: &str,
+        now: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM endpoint_cookie WHERE endpoint_id = ? AND expires_at <= ?",
+            endpoint_id,
+            now
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Rewrites the value of every cookie with `reseal`, used when the
+    /// master password is set or changed.
+    pub async fn reseal_values<F>(
+        tx: &mut Transaction<'_, Sqlite>,
+        mut reseal: F,
+    ) -> Result<(), anyhow::Error>
+    where
+        F: FnMut(&mut String) -> Result<(), String>,
+    {
+        let rows = sqlx::query!(r#"SELECT id AS "id!", value FROM endpoint_cookie"#)
+            .fetch_all(&mut **tx)
+            .await?;
+        debug!("Re-encrypting {} cookies", rows.len());
+
+        for row in rows {
+            let mut value = row.value;
+            reseal(&mut value).map_err(anyhow::Error::msg)?;
+            sqlx::query!(
+                "UPDATE endpoint_cookie SET value = ? WHERE id = ?",
+                value,
+                row.id
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn delete(pool: &SqlitePool, id: &str) -> Result<(), anyhow::Error> {
+        debug!("Deleting cookie {}", id);
+
+        let result = sqlx::query!("DELETE FROM endpoint_cookie WHERE id = ?", id)
+            .execute(pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            warn!("Cookie {} not found", id);
+            return Err(anyhow::Error::msg(format!("Cookie not found: {}", id)));
+        }
+        Ok(())
+    }
+
+    /// Empties the cookie jar of an endpoint.
+    pub async fn delete_by_endpoint(
+        pool: &SqlitePool,
+        endpoint_id: &str,
+    ) -> Result<(), anyhow::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM endpoint_cookie WHERE endpoint_id = ?",
+            endpoint_id
+        )
+        .execute(pool)
+        .await?;
+        info!(
+            "Cleared {} cookie(s) of endpoint {}",
+            result.rows_affected(),
+            endpoint_id
+        );
+        Ok(())
+    }
+}
+
+fn open_value(mut cookie: EndpointCookie) -> EndpointCookie {
+    if let Err(e) = secret_vault::open_secret(&mut cookie.value) {
+        error!("Failed to decrypt cookie {}: {}", cookie.id, e);
+    }
+    cookie
+}
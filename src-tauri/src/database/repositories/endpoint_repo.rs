@@ -1,10 +1,13 @@
-use crate::database::entities::endpoint_entity::{Endpoint, EndpointRow};
+use crate::common::secret_vault;
+use crate::database::entities::endpoint_entity::{
+    AuthConfig, Endpoint, EndpointConfig, EndpointRow,
+};
 use crate::models::common::pagination::{PaginatedResponse, PaginationParams};
 use crate::models::endpoint_model::{
     CreateEndpointDto, DeleteEndpointDto, EndpointFilter, UpdateEndpointDto,
 };
 use log::{debug, error, warn};
-use sqlx::{QueryBuilder, Row, Sqlite, SqlitePool};
+use sqlx::{QueryBuilder, Row, Sqlite, SqlitePool, Transaction};
 use uuid::Uuid;
 
 pub struct EndpointRepository;
@@ -149,11 +152,17 @@ impl EndpointRepository {
 
     pub async fn create(
         pool: &SqlitePool,
-        dto: CreateEndpointDto,
+        mut dto: CreateEndpointDto,
     ) -> Result<Endpoint, sqlx::Error> {
         let id = Uuid::new_v4().to_string();
         debug!("Creating endpoint with id: {}, name: {}", id, dto.name);
 
+        secret_vault::seal_secrets(dto.auth.as_mut(), dto.config.as_mut())
+            .map_err(|e| sqlx::Error::Encode(e.into()))?;
+        if let Some(config) = &mut dto.config {
+            config.clear_schema_cache();
+        }
+
         let config_str = dto.config_str();
         let headers_str = dto.headers_str();
         let auth_str = dto.auth_str();
@@ -221,10 +230,16 @@ impl EndpointRepository {
 
     pub async fn update(
         pool: &SqlitePool,
-        dto: UpdateEndpointDto,
+        mut dto: UpdateEndpointDto,
     ) -> Result<Endpoint, anyhow::Error> {
         debug!("Updating endpoint with id: {}", dto.id);
 
+        secret_vault::seal_secrets(dto.auth.as_mut(), dto.config.as_mut())
+            .map_err(anyhow::Error::msg)?;
+        if let Some(config) = &mut dto.config {
+            config.clear_schema_cache();
+        }
+
         let mut tx = pool.begin().await?;
         let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
             r#"
@@ -291,6 +306,114 @@ impl EndpointRepository {
         }
     }
 
+    /// Stores the introspected schema of an endpoint. Only the cache columns
+    /// are written, so edits made to the endpoint meanwhile are kept.
+    pub async fn update_schema_cache(
+        pool: &SqlitePool,
+        id: &str,
+        schema: &str,
+        cached_at: &str,
+    ) -> Result<(), anyhow::Error> {
+        debug!("Updating schema cache of endpoint {}", id);
+
+        let result = sqlx::query!(
+            "UPDATE endpoint SET schema_cache = ?, schema_cached_at = ? WHERE id = ?",
+            schema,
+            cached_at,
+            id
+        )
+        .execute(pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            warn!("Endpoint not found for id: {}", id);
+            return Err(anyhow::anyhow!("Endpoint not found: {}", id));
+        }
+        Ok(())
+    }
+
+    /// Stores the OAuth2 tokens of an endpoint. Only the token fields of its
+    /// auth are written, so edits made to the endpoint meanwhile are kept.
+    pub async fn update_oauth_tokens(
+        pool: &SqlitePool,
+        id: &str,
+        mut token: Option<String>,
+        mut refresh_token: Option<String>,
+        expires_at: Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        debug!("Updating OAuth2 tokens of endpoint {}", id);
+
+        for secret in [&mut token, &mut refresh_token].into_iter().flatten() {
+            secret_vault::seal_secret(secret).map_err(anyhow::Error::msg)?;
+        }
+        let result = sqlx::query!(
+            r#"
+            UPDATE endpoint SET auth = json_set(
+                auth,
+                '$.token', ?,
+                '$.oauth_refresh_token', ?,
+                '$.oauth_token_expires_at', ?
+            )
+            WHERE id = ? AND auth IS NOT NULL
+            "#,
+            token,
+            refresh_token,
+            expires_at,
+            id
+        )
+        .execute(pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            warn!("Endpoint not found or without auth for id: {}", id);
+            return Err(anyhow::anyhow!("Endpoint has no authentication: {}", id));
+        }
+        Ok(())
+    }
+
+    /// Rewrites the secrets of every endpoint with `reseal`, used when the
+    /// master password is set or changed.
+    pub async fn reseal_secrets<F>(
+        tx: &mut Transaction<'_, Sqlite>,
+        mut reseal: F,
+    ) -> Result<(), anyhow::Error>
+    where
+        F: FnMut(Option<&mut AuthConfig>, Option<&mut EndpointConfig>) -> Result<(), String>,
+    {
+        let rows = sqlx::query!(r#"SELECT id AS "id!", auth, config FROM endpoint"#)
+            .fetch_all(&mut **tx)
+            .await?;
+        debug!("Re-encrypting secrets of {} endpoints", rows.len());
+
+        for row in rows {
+            let id = row.id;
+            let auth_json = row.auth;
+            let config_json = row.config.unwrap_or_default();
+
+            let mut auth = auth_json
+                .as_deref()
+                .map(serde_json::from_str::<AuthConfig>)
+                .transpose()?;
+            // Unreadable configs are left alone like in `Endpoint::try_from`.
+            let mut config = serde_json::from_str::<EndpointConfig>(&config_json).ok();
+            reseal(auth.as_mut(), config.as_mut()).map_err(anyhow::Error::msg)?;
+
+            let auth_json = auth.map(|auth| serde_json::to_string(&auth)).transpose()?;
+            let config_json = match config {
+                Some(config) => serde_json::to_string(&config)?,
+                None => config_json,
+            };
+            sqlx::query!(
+                "UPDATE endpoint SET auth = ?, config = ? WHERE id = ?",
+                auth_json,
+                config_json,
+                id
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn delete(pool: &SqlitePool, dto: DeleteEndpointDto) -> Result<(), anyhow::Error> {
         debug!("Deleting endpoint with id: {}", dto.id);
 
@@ -1,7 +1,11 @@
+pub mod endpoint_cookie_repo;
 pub mod endpoint_repo;
 pub mod request_history_repo;
+pub mod secret_vault_repo;
 pub mod settings_repo;
 
+pub use endpoint_cookie_repo::EndpointCookieRepository;
 pub use endpoint_repo::EndpointRepository;
 pub use request_history_repo::RequestHistoryRepository;
+pub use secret_vault_repo::SecretVaultRepository;
 pub use settings_repo::SettingsRepository;
@@ -1,5 +1,5 @@
 use log::{debug, error, info, warn};
-use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+use sqlx::{types::Json, QueryBuilder, Sqlite, SqlitePool};
 use uuid::Uuid;
 
 use crate::{
@@ -193,6 +193,14 @@ impl RequestHistoryRepository {
             debug!("Updating query field");
         }
 
+        if let Some(timings) = &dto.timings {
+            separated
+                .push("timings = ")
+                .push_bind_unseparated(Json(timings));
+            update_field_count += 1;
+            debug!("Updating timings field");
+        }
+
         if update_field_count == 0 {
             warn!("No fields to update for request history id: {}", dto.id);
             return Err(anyhow::Error::msg("No fields to update"));
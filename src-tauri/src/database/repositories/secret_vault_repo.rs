@@ -0,0 +1,43 @@
+use crate::database::entities::secret_vault_entity::SecretVaultRecord;
+use log::debug;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+pub struct SecretVaultRepository;
+
+impl SecretVaultRepository {
+    pub async fn find(pool: &SqlitePool) -> Result<Option<SecretVaultRecord>, sqlx::Error> {
+        debug!("Fetching secret vault");
+
+        sqlx::query_as!(
+            SecretVaultRecord,
+            "SELECT kdf_salt, verifier FROM secret_vault WHERE id = 1"
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn save(
+        tx: &mut Transaction<'_, Sqlite>,
+        kdf_salt: &str,
+        verifier: &str,
+    ) -> Result<(), sqlx::Error> {
+        debug!("Saving secret vault");
+
+        sqlx::query!(
+            r#"
+            INSERT INTO secret_vault (id, kdf_salt, verifier)
+            VALUES (1, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET
+                kdf_salt = excluded.kdf_salt,
+                verifier = excluded.verifier,
+                updated_at = CURRENT_TIMESTAMP
+            "#,
+            kdf_salt,
+            verifier
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -6,6 +6,14 @@ mod common;
 mod database;
 mod models;
 
+use common::graphql_subscription::SubscriptionRegistry;
+use common::oauth2::OAuthTokenCache;
+use common::request_registry::RequestRegistry;
+use common::response_body;
+use common::secret_vault;
+use sqlx::SqlitePool;
+use tauri::Manager;
+
 #[tokio::main]
 async fn main() {
     tauri::Builder::default()
@@ -23,12 +31,21 @@ async fn main() {
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_os::init())
         .plugin(tauri_plugin_fs::init())
+        .plugin(tauri_plugin_opener::init())
+        .manage(SubscriptionRegistry::default())
+        .manage(RequestRegistry::default())
+        .manage(OAuthTokenCache::default())
         .setup(|app| {
+            response_body::clear_temp_files();
             let app_handle = app.handle().clone();
             tokio::spawn(async move {
                 database::setup(&app_handle)
                     .await
                     .expect("error while setting up the database");
+                let pool = app_handle.state::<SqlitePool>();
+                if let Err(e) = secret_vault::load(&pool).await {
+                    log::error!("{}", e);
+                }
             });
             Ok(())
         })
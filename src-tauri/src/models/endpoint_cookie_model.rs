@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+use typeshare::typeshare;
+
+/// Data transfer object for adding a cookie to an endpoint's jar or
+/// editing one
+#[typeshare]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SaveEndpointCookieDto {
+    /// Cookie to edit; without it the cookie is added, replacing one with
+    /// the same name, domain and path
+    pub id: Option<String>,
+    /// Endpoint whose jar holds the cookie
+    pub endpoint_id: String,
+    /// Cookie name
+    pub name: String,
+    /// Cookie value
+    pub value: String,
+    /// Host the cookie is sent to; subdomains are included unless
+    /// `host_only` is set
+    pub domain: String,
+    /// Only send the cookie to `domain` itself, true by default
+    pub host_only: Option<bool>,
+    /// Only send the cookie to URLs below this path, `/` by default
+    pub path: Option<String>,
+    /// RFC 3339 expiry time; without it the cookie never expires
+    pub expires_at: Option<String>,
+    /// Only send the cookie over HTTPS
+    pub secure: Option<bool>,
+    /// Marks the cookie as HttpOnly
+    pub http_only: Option<bool>,
+    /// `SameSite` attribute: `Strict`, `Lax` or `None`
+    pub same_site: Option<String>,
+}
@@ -1,3 +1,4 @@
 pub mod common;
+pub mod endpoint_cookie_model;
 pub mod endpoint_model;
 pub mod request_history_model;
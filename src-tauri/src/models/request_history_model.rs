@@ -3,6 +3,7 @@ use sqlx::types::Json;
 use typeshare::typeshare;
 
 use crate::common::http_method::HttpMethod;
+use crate::common::http_timing::HttpTimings;
 
 #[typeshare]
 #[derive(Debug, Serialize, Deserialize)]
@@ -44,6 +45,8 @@ pub struct UpdateRequestHistoryDto {
     pub body: Option<Json<serde_json::Value>>,
     /// Query parameters included in the request
     pub query: Option<String>,
+    /// Timing breakdown of the last execution of the request
+    pub timings: Option<HttpTimings>,
 }
 
 #[typeshare]
@@ -1,4 +1,5 @@
 import {
+  ClientCertificate,
   CreateEndpointDto,
   DeleteEndpointDto,
   Endpoint,
@@ -63,4 +64,28 @@ export class EndpointBridge {
       throw error
     }
   }
+
+  static async importClientCertificate(
+    certificate: ClientCertificate
+  ): Promise<ClientCertificate> {
+    try {
+      return await invoke<ClientCertificate>('import_client_certificate', {
+        certificate,
+      })
+    } catch (error) {
+      console.error('Failed to import client certificate:', error)
+      throw error
+    }
+  }
+
+  static async authorizeEndpoint(endpointId: string): Promise<void> {
+    try {
+      return await invoke<void>('authorize_endpoint', {
+        endpointId,
+      })
+    } catch (error) {
+      console.error('Failed to authorize endpoint:', error)
+      throw error
+    }
+  }
 }
@@ -0,0 +1,55 @@
+import {
+  EndpointCookie,
+  SaveEndpointCookieDto,
+} from '@/generated/typeshare-types'
+import { invoke } from '@tauri-apps/api/core'
+
+export class EndpointCookieBridge {
+  static async listEndpointCookies(
+    endpointId: string
+  ): Promise<EndpointCookie[]> {
+    try {
+      return await invoke<EndpointCookie[]>('get_endpoint_cookies', {
+        endpointId,
+      })
+    } catch (error) {
+      console.error('Failed to list endpoint cookies:', error)
+      throw error
+    }
+  }
+
+  static async saveEndpointCookie(
+    dto: SaveEndpointCookieDto
+  ): Promise<EndpointCookie> {
+    try {
+      return await invoke<EndpointCookie>('save_endpoint_cookie', {
+        dto,
+      })
+    } catch (error) {
+      console.error('Failed to save endpoint cookie:', error)
+      throw error
+    }
+  }
+
+  static async deleteEndpointCookie(id: string): Promise<void> {
+    try {
+      await invoke<void>('delete_endpoint_cookie', {
+        id,
+      })
+    } catch (error) {
+      console.error('Failed to delete endpoint cookie:', error)
+      throw error
+    }
+  }
+
+  static async clearEndpointCookies(endpointId: string): Promise<void> {
+    try {
+      await invoke<void>('clear_endpoint_cookies', {
+        endpointId,
+      })
+    } catch (error) {
+      console.error('Failed to clear endpoint cookies:', error)
+      throw error
+    }
+  }
+}
@@ -1,18 +1,55 @@
 import {
+  GraphQLOperation,
   HttpResponse,
+  IntrospectedSchema,
   SendGraphQLRequestPayload,
+  SubscriptionEvent,
 } from '@/generated/typeshare-types'
-import { invoke } from '@tauri-apps/api/core'
+import { Channel, invoke } from '@tauri-apps/api/core'
 
 export class GraphQLBridge {
-  static async send_graphql_request(data: SendGraphQLRequestPayload) {
+  static async send_graphql_request(
+    data: SendGraphQLRequestPayload,
+    onEvent?: (event: SubscriptionEvent) => void,
+    requestId?: string
+  ) {
+    // Results of streamed responses are pushed through this channel
+    const channel = new Channel<SubscriptionEvent>()
+    if (onEvent) {
+      channel.onmessage = onEvent
+    }
     try {
       return await invoke<HttpResponse>('send_graphql_request', {
         data,
+        onEvent: channel,
+        requestId,
       })
     } catch (error) {
       console.error('Failed to send GraphQL request:', error)
       throw error
     }
   }
+
+  static async get_graphql_operations(query: string) {
+    try {
+      return await invoke<GraphQLOperation[]>('get_graphql_operations', {
+        query,
+      })
+    } catch (error) {
+      console.error('Failed to parse GraphQL operations:', error)
+      throw error
+    }
+  }
+
+  static async introspect_endpoint(params: {
+    endpointId: string
+    forceRefresh?: boolean
+  }) {
+    try {
+      return await invoke<IntrospectedSchema>('introspect_endpoint', params)
+    } catch (error) {
+      console.error('Failed to introspect endpoint:', error)
+      throw error
+    }
+  }
 }
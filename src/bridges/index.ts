@@ -1,5 +1,8 @@
 export * from './endpoint-bridge'
+export * from './endpoint-cookie-bridge'
 export * from './graphql-bridge'
 export * from './proxy-bridge'
 export * from './request-history-bridge'
+export * from './secret-vault-bridge'
 export * from './settings-bridge'
+export * from './subscription-bridge'
@@ -2,14 +2,46 @@ import { HttpRequest, HttpResponse } from '@/generated/typeshare-types'
 import { invoke } from '@tauri-apps/api/core'
 
 export class ProxyHttpBridge {
-  static async proxy_http_request(request: HttpRequest): Promise<HttpResponse> {
+  static async proxy_http_request(
+    request: HttpRequest,
+    requestId?: string
+  ): Promise<HttpResponse> {
     try {
       return await invoke<HttpResponse>('proxy_http_request', {
         request,
+        requestId,
       })
     } catch (error) {
       console.error('Failed to send HTTP request:', error)
       throw error
     }
   }
+
+  static async save_response_to_file(
+    request: HttpRequest,
+    path: string,
+    requestId?: string
+  ): Promise<HttpResponse> {
+    try {
+      return await invoke<HttpResponse>('save_response_to_file', {
+        request,
+        path,
+        requestId,
+      })
+    } catch (error) {
+      console.error('Failed to save response to file:', error)
+      throw error
+    }
+  }
+
+  static async cancel_request(requestId: string): Promise<void> {
+    try {
+      return await invoke<void>('cancel_request', {
+        requestId,
+      })
+    } catch (error) {
+      console.error('Failed to cancel request:', error)
+      throw error
+    }
+  }
 }
@@ -0,0 +1,55 @@
+import { SecretVaultStatus } from '@/generated/typeshare-types'
+import { invoke } from '@tauri-apps/api/core'
+
+export class SecretVaultBridge {
+  static async get_secret_vault_status(): Promise<SecretVaultStatus> {
+    try {
+      return await invoke<SecretVaultStatus>('get_secret_vault_status')
+    } catch (error) {
+      console.error('Failed to get secret vault status:', error)
+      throw error
+    }
+  }
+
+  static async set_master_password(password: string): Promise<void> {
+    try {
+      return await invoke<void>('set_master_password', { password })
+    } catch (error) {
+      console.error('Failed to set master password:', error)
+      throw error
+    }
+  }
+
+  static async change_master_password(
+    currentPassword: string,
+    newPassword: string
+  ): Promise<void> {
+    try {
+      return await invoke<void>('change_master_password', {
+        currentPassword,
+        newPassword,
+      })
+    } catch (error) {
+      console.error('Failed to change master password:', error)
+      throw error
+    }
+  }
+
+  static async unlock_secret_vault(password: string): Promise<void> {
+    try {
+      return await invoke<void>('unlock_secret_vault', { password })
+    } catch (error) {
+      console.error('Failed to unlock secret vault:', error)
+      throw error
+    }
+  }
+
+  static async lock_secret_vault(): Promise<void> {
+    try {
+      return await invoke<void>('lock_secret_vault')
+    } catch (error) {
+      console.error('Failed to lock secret vault:', error)
+      throw error
+    }
+  }
+}
@@ -0,0 +1,35 @@
+import {
+  StartSubscriptionPayload,
+  SubscriptionEvent,
+} from '@/generated/typeshare-types'
+import { Channel, invoke } from '@tauri-apps/api/core'
+
+export class SubscriptionBridge {
+  static async start_subscription(
+    data: StartSubscriptionPayload,
+    onEvent: (event: SubscriptionEvent) => void
+  ): Promise<string> {
+    const channel = new Channel<SubscriptionEvent>()
+    channel.onmessage = onEvent
+    try {
+      return await invoke<string>('start_subscription', {
+        data,
+        onEvent: channel,
+      })
+    } catch (error) {
+      console.error('Failed to start subscription:', error)
+      throw error
+    }
+  }
+
+  static async stop_subscription(subscriptionId: string): Promise<void> {
+    try {
+      return await invoke<void>('stop_subscription', {
+        subscriptionId,
+      })
+    } catch (error) {
+      console.error('Failed to stop subscription:', error)
+      throw error
+    }
+  }
+}
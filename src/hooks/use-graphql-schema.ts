@@ -1,7 +1,7 @@
+import { GraphQLBridge } from '@/bridges'
 import { Endpoint } from '@/generated/typeshare-types'
-import { fetchGraphqlSchema, formatHeadersStringToObject } from '@/lib'
 import { usePageGraphQLSchemaStore } from '@/stores/page-graphql-schema-state'
-import { GraphQLSchema } from 'graphql'
+import { buildClientSchema, GraphQLSchema } from 'graphql'
 import { useCallback, useEffect, useRef } from 'react'
 
 const AUTO_REFRESH_INTERVAL = 1 * 60 * 1000
@@ -69,9 +69,12 @@ export function useGraphQLSchema(data: {
   }, [endpoint, enableAutoRefresh, clearAutoRefreshTimer])
 
   const fetchSchemaFromNetwork = useCallback(
-    async (params: { isTriggerByAutoRefresh?: boolean }): Promise<void> => {
-      const { isTriggerByAutoRefresh = false } = params
-      if (!endpoint?.url) return
+    async (params: {
+      isTriggerByAutoRefresh?: boolean
+      forceRefresh?: boolean
+    }): Promise<void> => {
+      const { isTriggerByAutoRefresh = false, forceRefresh = true } = params
+      if (!endpoint?.id) return
 
       if (abortControllerRef.current) {
         abortControllerRef.current.abort()
@@ -88,19 +91,16 @@ export function useGraphQLSchema(data: {
       let retries = 0
       const attemptFetch = async (): Promise<void> => {
         try {
-          const parsedSchema = await fetchGraphqlSchema({
-            endpoint: {
-              ...endpoint,
-              headers: formatHeadersStringToObject(endpoint.headers || ''),
-            },
+          // The backend serves the cached schema unless a refresh is forced,
+          // and stores freshly introspected schemas for the next load
+          const introspected = await GraphQLBridge.introspect_endpoint({
+            endpointId: endpoint.id,
+            forceRefresh,
           })
-          if (!parsedSchema) {
-            throw new Error('Fetched schema is null or undefined')
-          }
-          setSchema(parsedSchema)
+          setSchema(buildClientSchema(JSON.parse(introspected.schema)))
           setLoading(false)
           setError(null)
-          setLastFetchTime(Date.now())
+          setLastFetchTime(Date.parse(introspected.fetched_at))
           initializingRef.current = false
 
           if (enableAutoRefresh) {
@@ -163,6 +163,7 @@ export function useGraphQLSchema(data: {
 
     fetchSchemaFromNetwork({
       isTriggerByAutoRefresh: false,
+      forceRefresh: false,
     })
 
     return () => {
@@ -1,6 +1,21 @@
 import { ProxyHttpBridge } from '@/bridges'
+import { BodyEncoding, HttpBody } from '@/generated/typeshare-types'
 import { createGraphiQLFetcher } from '@graphiql/toolkit'
 
+// Bodies the backend cannot send, such as blobs, are dropped
+function toHttpBody(body?: BodyInit | null): HttpBody | undefined {
+  if (typeof body === 'string') {
+    return { type: 'text', content: body }
+  }
+  if (body instanceof URLSearchParams) {
+    return {
+      type: 'urlEncoded',
+      content: Array.from(body, ([name, value]) => ({ name, value })),
+    }
+  }
+  return undefined
+}
+
 export function timeoutFetch(params: {
   url: string
   options?: RequestInit
@@ -9,12 +24,20 @@ export function timeoutFetch(params: {
 }): Promise<Response> {
   const { url, options, timeout = 10000, signal } = params
   return new Promise((resolve, reject) => {
+    // Lets the backend abort the request instead of leaving it running
+    const requestId = crypto.randomUUID()
+    const cancel = () => {
+      ProxyHttpBridge.cancel_request(requestId).catch(() => {})
+    }
+
     const timeoutId = setTimeout(() => {
+      cancel()
       reject(new Error(`Request timed out after ${timeout}ms`))
     }, timeout)
 
     const onAbort = () => {
       clearTimeout(timeoutId)
+      cancel()
       reject(new Error('Request aborted'))
     }
 
@@ -25,18 +48,30 @@ export function timeoutFetch(params: {
 
     signal?.addEventListener('abort', onAbort)
 
-    ProxyHttpBridge.proxy_http_request({
-      url,
-      method: options?.method || 'GET',
-      headers: options?.headers as Record<string, string>,
-      body: typeof options?.body === 'string' ? options.body : undefined,
-    })
+    ProxyHttpBridge.proxy_http_request(
+      {
+        url,
+        method: options?.method || 'GET',
+        headers: Array.from(new Headers(options?.headers), ([name, value]) => ({
+          name,
+          value,
+        })),
+        body: toHttpBody(options?.body),
+      },
+      requestId
+    )
       .then((res) => {
         clearListeners()
-        const response = new Response(res.body, {
+        const body =
+          res.body_encoding === BodyEncoding.Base64
+            ? Uint8Array.from(atob(res.body), (char) => char.charCodeAt(0))
+            : res.body
+        const response = new Response(body, {
           status: res.status_code,
-          statusText: '',
-          headers: new Headers(res.headers),
+          statusText: res.status_text,
+          headers: new Headers(
+            res.headers.map(({ name, value }) => [name, value])
+          ),
         })
         resolve(response)
       })
@@ -18,20 +18,26 @@ export async function fetchGraphqlSchema(params: {
   const { endpoint, isCheckConnectivity } = params
 
   try {
+    const headers = getGraphQLRequestHeaders({
+      endpoint: {
+        url: endpoint.url,
+        auth: endpoint.auth,
+      },
+      customHeaders: endpoint.headers,
+    })
     const schemaResponse = await ProxyHttpBridge.proxy_http_request({
       url: endpoint.url,
       method: 'POST',
-      headers: getGraphQLRequestHeaders({
-        endpoint: {
-          url: endpoint.url,
-          auth: endpoint.auth,
-        },
-        customHeaders: endpoint.headers,
-      }),
-      body: JSON.stringify({
-        query: getIntrospectionQuery(),
-        operationName: 'IntrospectionQuery',
-      }),
+      headers:
+        headers &&
+        Object.entries(headers).map(([name, value]) => ({ name, value })),
+      body: {
+        type: 'json',
+        content: JSON.stringify({
+          query: getIntrospectionQuery(),
+          operationName: 'IntrospectionQuery',
+        }),
+      },
     })
 
     if (schemaResponse.status_code < 200 || schemaResponse.status_code >= 300) {
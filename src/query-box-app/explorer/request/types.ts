@@ -1,4 +1,12 @@
+import { SendGraphQLRequestPayload } from '@/generated/typeshare-types'
+
 export interface RunGraphQLQueryParams {
   codeString: string
   definitionNameValue: string
 }
+
+export interface SendGraphQLRequestVariables {
+  payload: SendGraphQLRequestPayload
+  /** Request history entry the timings of the response are stored with */
+  requestHistoryId?: string
+}
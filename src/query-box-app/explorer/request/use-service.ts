@@ -4,8 +4,8 @@ import {
   QueryEditorCodeLensOperation,
   QueryEditorOnUpdateLensOperationsActionParameters,
 } from '@/components/query-editor/types'
+import { HttpTimings } from '@/generated/typeshare-types'
 import { useGraphQLSchema } from '@/hooks'
-import { getGraphQLRequestHeaders } from '@/lib'
 import {
   useEndpointSelectedStateStore,
   useGraphQLExplorerPageStore,
@@ -14,7 +14,7 @@ import { useMutation } from '@tanstack/react-query'
 import { List } from 'immutable'
 import { useCallback, useEffect, useState } from 'react'
 import { toast } from 'sonner'
-import { RunGraphQLQueryParams } from './types'
+import { RunGraphQLQueryParams, SendGraphQLRequestVariables } from './types'
 
 export const useRequestService = () => {
   const [codeLensOperations, setCodeLensOperations] = useState<
@@ -58,10 +58,37 @@ export const useRequestService = () => {
     setResponse(null)
   }, [currentPageSelectedEndpoint])
 
+  // Keep the timing breakdown with the request history it was executed
+  // from, without switching back to it if another tab became active
+  const storeRequestTimings = useCallback(
+    async (id: string, timings: HttpTimings) => {
+      const history = await RequestHistoryBridge.updateRequestHistory({
+        id,
+        timings,
+      })
+      const { requestHistories, activeRequestHistory } =
+        useGraphQLExplorerPageStore.getState()
+      setRequestHistories(
+        requestHistories.map((item) => (item.id === id ? history : item))
+      )
+      if (activeRequestHistory?.id === id) {
+        setActiveRequestHistory({
+          requestHistory: history,
+          updateActiveBackend: false,
+        })
+      }
+    },
+    []
+  )
+
   const { mutate: sendGraphQLRequest, isPending } = useMutation({
-    mutationFn: GraphQLBridge.send_graphql_request,
-    onSuccess: (data) => {
+    mutationFn: ({ payload }: SendGraphQLRequestVariables) =>
+      GraphQLBridge.send_graphql_request(payload),
+    onSuccess: (data, { requestHistoryId }) => {
       setResponse(data)
+      if (requestHistoryId && data.timings) {
+        storeRequestTimings(requestHistoryId, data.timings).catch(() => {})
+      }
     },
     onError: (error) => {
       toast.error('Request failed: ', {
@@ -152,11 +179,14 @@ export const useRequestService = () => {
 
       // Send the GraphQL request using the GraphQLBridge
       sendGraphQLRequest({
-        endpoint: latestEndpoint.url ?? '',
-        query: codeStringValue,
-        headers: getGraphQLRequestHeaders({
-          endpoint: latestEndpoint,
-        }),
+        payload: {
+          endpoint: latestEndpoint.url ?? '',
+          query: codeStringValue,
+          operationName: definitionNameValue || undefined,
+          // Headers, auth and network options are applied by the backend
+          endpointId: latestEndpoint.id,
+        },
+        requestHistoryId: latestActiveRequestHistory?.id,
       })
 
       // Update the request history with the new query